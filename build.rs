@@ -5,6 +5,17 @@ use std::io::BufRead;
 const NCNN_REPO_URL: &str = "https://github.com/Tencent/ncnn";
 const NCNN_COMMIT_HASH: &str = "066614351391d309c96ae1e00c6fb1bd873b4949";
 
+// glslang libraries in link order. Newer glslang releases merge some of
+// these into glslang itself, so only the ones that were built are linked.
+const GLSLANG_LIBS: [&str; 6] = [
+    "MachineIndependent",
+    "SPIRV",
+    "GenericCodeGen",
+    "OSDependent",
+    "OGLCompiler",
+    "glslang",
+];
+
 fn execute_command(command: &mut Command) -> Result<(), String> {
     let status = command.status().map_err(|e| e.to_string())?;
     if !status.success() {
@@ -13,13 +24,49 @@ fn execute_command(command: &mut Command) -> Result<(), String> {
     Ok(())
 }
 
+// REALCUGAN_NCNN_REPO points the clone at a mirror or an ssh url, e.g.
+// git@github.com:Tencent/ncnn.git. Submodules still use the urls in ncnn's
+// .gitmodules.
+fn ncnn_repo_url() -> String {
+    println!("cargo:rerun-if-env-changed=REALCUGAN_NCNN_REPO");
+    std::env::var("REALCUGAN_NCNN_REPO")
+        .ok()
+        .filter(|url| !url.is_empty())
+        .unwrap_or_else(|| NCNN_REPO_URL.to_string())
+}
+
+// Only the pinned commit is fetched, since nothing else is ever checked out.
+// REALCUGAN_NCNN_SHALLOW=0 does a full clone instead, for mirrors that do
+// not allow fetching a commit by its hash.
+fn ncnn_shallow() -> bool {
+    println!("cargo:rerun-if-env-changed=REALCUGAN_NCNN_SHALLOW");
+    !matches!(std::env::var("REALCUGAN_NCNN_SHALLOW").as_deref(), Ok("0") | Ok("off") | Ok("OFF") | Ok("false"))
+}
+
+fn fetch_ncnn(target_dir: &str, url: &str) -> Result<(), String> {
+    std::fs::create_dir_all(target_dir).map_err(|e| e.to_string())?;
+    let git = |args: &[&str]| execute_command(Command::new("git").current_dir(target_dir).args(args));
+    git(&["init", "-q"])?;
+    git(&["remote", "add", "origin", url])?;
+    git(&["fetch", "--depth", "1", "origin", NCNN_COMMIT_HASH])?;
+    git(&["checkout", "-q", "FETCH_HEAD"])?;
+    git(&["submodule", "update", "--init", "--recursive", "--depth", "1"])
+}
+
 fn clone_ncnn(target_dir: &str) -> Result<(), String> {
     if std::fs::exists(target_dir).unwrap() {
         return Ok(())
     }
+    let url = ncnn_repo_url();
+    if ncnn_shallow() {
+        // a partial checkout would be taken for a finished one by the next build
+        return fetch_ncnn(target_dir, &url).inspect_err(|_| {
+            let _ = std::fs::remove_dir_all(target_dir);
+        })
+    }
     execute_command(
         Command::new("git")
-            .args(&["clone", "--recursive", NCNN_REPO_URL])
+            .args(&["clone", "--recursive", &url])
             .arg(target_dir)
     )?;
 
@@ -31,20 +78,43 @@ fn clone_ncnn(target_dir: &str) -> Result<(), String> {
     Ok(())
 }
 
+// REALCUGAN_NCNN_LTO=0 turns link time optimization off for faster
+// development builds of ncnn.
+fn ncnn_lto() -> &'static str {
+    println!("cargo:rerun-if-env-changed=REALCUGAN_NCNN_LTO");
+    match std::env::var("REALCUGAN_NCNN_LTO").as_deref() {
+        Ok("0") | Ok("off") | Ok("OFF") | Ok("false") => "OFF",
+        _ => "ON",
+    }
+}
+
+// REALCUGAN_NCNN_BUILD_TYPE selects the cmake build type of ncnn, e.g. Debug
+// or RelWithDebInfo for native symbols. Cargo's profile is not used, since a
+// debug ncnn is too slow to be the default for debug builds of the crate.
+fn ncnn_build_type() -> String {
+    println!("cargo:rerun-if-env-changed=REALCUGAN_NCNN_BUILD_TYPE");
+    std::env::var("REALCUGAN_NCNN_BUILD_TYPE").unwrap_or_else(|_| "Release".to_string())
+}
+
 fn configure_ncnn_build(target_dir: &str) -> Config {
     let mut config = Config::new(target_dir);
     config.define("NCNN_BUILD_TOOLS", "OFF")
           .define("NCNN_BUILD_EXAMPLES", "OFF")
           .define("NCNN_BUILD_BENCHMARK", "OFF")
-          .define("NCNN_ENABLE_LTO", "ON")
+          .define("NCNN_ENABLE_LTO", ncnn_lto())
           .define("NCNN_SHARED_LIB", "OFF")
           .define("NCNN_VULKAN", "ON")
           .define("NCNN_SYSTEM_GLSLANG", "OFF")
-          .define("CMAKE_BUILD_TYPE", "Release");
+          .define("CMAKE_BUILD_TYPE", ncnn_build_type());
     config
 }
 
-fn disable_logs(target_dir: &str) -> Result<(), std::io::Error> {
+// With the ncnn-logs feature NCNN_LOGE forwards to realcugan_ncnn_logf in
+// wrapper.cpp, otherwise it expands to nothing. Both replacements are single
+// lines, so the checkout can be rewritten again when the feature changes.
+fn rewrite_logs(target_dir: &str) -> Result<(), std::io::Error> {
+    const LOG_DECLARATION: &str = "extern \"C\" void realcugan_ncnn_logf(const char *format, ...);";
+
     let platform_file = format!("{}/src/platform.h.in", target_dir);
     let file = std::fs::File::open(&platform_file)?;
     let reader = std::io::BufReader::new(file);
@@ -55,9 +125,15 @@ fn disable_logs(target_dir: &str) -> Result<(), std::io::Error> {
     for line_result in reader.lines() {
         let line = line_result?;
 
-        if line.contains("#define NCNN_LOGE(...) do {") {
-            skip_lines = true;
-            text += &format!("#define NCNN_LOGE(...)\n");
+        if line.contains(LOG_DECLARATION) {
+            continue
+        } else if line.contains("#define NCNN_LOGE(...)") {
+            skip_lines = line.contains("do {");
+            if cfg!(feature = "ncnn-logs") {
+                text += &format!("{}\n#define NCNN_LOGE(...) realcugan_ncnn_logf(__VA_ARGS__)\n", LOG_DECLARATION);
+            } else {
+                text += "#define NCNN_LOGE(...)\n";
+            }
             continue
         } else if (line.contains("#endif") || line.contains("#else")) && skip_lines {
             skip_lines = false;
@@ -72,42 +148,112 @@ fn disable_logs(target_dir: &str) -> Result<(), std::io::Error> {
     Ok(())
 }
 
-fn build_ncnn(output: &str) -> Result<(), String> {
-    let target_dir = format!("{}/ncnn", output);
-
+fn link_runtime_libs() {
     println!("cargo:rustc-link-lib={}", "stdc++");
     println!("cargo:rustc-link-lib={}", "pthread");
     println!("cargo:rustc-link-lib={}", "omp");
     println!("cargo:rustc-link-lib={}", "vulkan");
+}
 
+fn build_ncnn(output: &str) -> Result<(), String> {
+    let target_dir = format!("{}/ncnn", output);
+
+    link_runtime_libs();
     clone_ncnn(&target_dir)?;
-    disable_logs(&target_dir).map_err(|r| r.to_string())?;
-    configure_ncnn_build(&target_dir)
-        .cflag("-O3")
-        .cxxflag("-O3")
-        .build();
-
-    println!("cargo:rustc-link-search=native={}/lib64", output);
-    println!("cargo:rustc-link-lib=static={}", "MachineIndependent");
-    println!("cargo:rustc-link-lib=static={}", "SPIRV");
-    println!("cargo:rustc-link-lib=static={}", "GenericCodeGen");
-    println!("cargo:rustc-link-lib=static={}", "OSDependent");
-    println!("cargo:rustc-link-lib=static={}", "OGLCompiler");
-    println!("cargo:rustc-link-lib=static={}", "glslang");
+    rewrite_logs(&target_dir).map_err(|r| r.to_string())?;
+    let mut config = configure_ncnn_build(&target_dir);
+    if ncnn_build_type() == "Release" {
+        config.cflag("-O3").cxxflag("-O3");
+    }
+    config.build();
+
+    let lib_dirs = [format!("{}/lib64", output), format!("{}/lib", output)];
+    println!("cargo:rustc-link-search=native={}", lib_dirs[0]);
+    link_glslang(&lib_dirs);
     println!("cargo:rustc-link-lib=static={}", "ncnn");
     Ok(())
 }
 
+// REALCUGAN_NCNN_PREBUILT points at the install prefix of a static ncnn built
+// elsewhere, with its libraries in lib or lib64 and its cmake config next to
+// them, so CI can build ncnn once and reuse it.
+fn prebuilt_ncnn() -> Option<String> {
+    println!("cargo:rerun-if-env-changed=REALCUGAN_NCNN_PREBUILT");
+    std::env::var("REALCUGAN_NCNN_PREBUILT").ok().filter(|prefix| !prefix.is_empty())
+}
+
+fn link_prebuilt_ncnn(prefix: &str) -> Result<(), String> {
+    if !std::path::Path::new(prefix).is_dir() {
+        return Err(format!("REALCUGAN_NCNN_PREBUILT is not a directory: {}", prefix));
+    }
+    link_runtime_libs();
+    let lib_dirs = [format!("{}/lib64", prefix), format!("{}/lib", prefix)];
+    for dir in &lib_dirs {
+        println!("cargo:rustc-link-search=native={}", dir);
+    }
+    link_glslang(&lib_dirs);
+    println!("cargo:rustc-link-lib=static={}", "ncnn");
+    Ok(())
+}
+
+fn link_glslang(lib_dirs: &[String]) {
+    for lib in GLSLANG_LIBS {
+        let exists = lib_dirs
+            .iter()
+            .any(|dir| std::path::Path::new(&format!("{}/lib{}.a", dir, lib)).exists());
+        if exists {
+            println!("cargo:rustc-link-lib=static={}", lib);
+        }
+    }
+}
+
+// Also returns the prefix from ncnn.pc, since the wrapper finds ncnn through
+// its cmake config and would otherwise only look in the default prefixes.
+fn link_system_ncnn() -> (String, Option<String>) {
+    match pkg_config::Config::new().probe("ncnn") {
+        Ok(library) => {
+            let prefix = pkg_config::get_variable("ncnn", "prefix").ok().filter(|prefix| !prefix.is_empty());
+            (format!("system {}", library.version), prefix)
+        }
+        Err(e) => {
+            println!("cargo:warning=pkg-config could not find ncnn, linking -lncnn from the default paths: {}", e);
+            println!("cargo:rustc-link-lib=dylib={}", "ncnn");
+            ("system".to_string(), None)
+        }
+    }
+}
+
+// Any rerun-if directive replaces cargo's default of rerunning on every
+// change in the package, so the wrapper sources are listed as well.
+fn rerun_if_sources_changed() {
+    for path in ["build.rs", "src/CMakeLists.txt", "src/cpp", "src/shaders"] {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+}
+
 fn main() {
+    rerun_if_sources_changed();
     let output = std::env::var("OUT_DIR").unwrap();
-    if cfg!(feature = "system-ncnn") {
-        println!("cargo:rustc-link-lib=dylib={}", "ncnn");
+    let prebuilt = prebuilt_ncnn().filter(|_| !cfg!(feature = "system-ncnn"));
+    let (ncnn_version, prefix) = if cfg!(feature = "system-ncnn") {
+        link_system_ncnn()
+    } else if let Some(prefix) = prebuilt {
+        if let Err(e) = link_prebuilt_ncnn(&prefix) {
+            panic!("Failed to link prebuilt ncnn: {}", e);
+        }
+        (format!("prebuilt {}", prefix), Some(prefix))
     } else {
         if let Err(e) = build_ncnn(&output) {
             panic!("Failed to build ncnn: {}", e);
         }
+        (NCNN_COMMIT_HASH.to_string(), None)
+    };
+    println!("cargo:rustc-env=REALCUGAN_NCNN_VERSION={}", ncnn_version);
+    let mut wrapper = Config::new("src");
+    if let Some(prefix) = &prefix {
+        wrapper.define("CMAKE_PREFIX_PATH", prefix);
     }
-    Config::new("src").build();
+    wrapper.build();
     println!("cargo:rustc-link-search=native={}/lib", &output);
     println!("cargo:rustc-link-lib=static={}", "realcugan-wrapper");
 }
\ No newline at end of file
@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use realcugan_rs::RealCugan;
+
+// The first four bytes are a little endian offset that picks where the rest
+// is split into param and bin, so both files get malformed independently and
+// the param is not limited to a few hundred bytes. Every input must return,
+// never crash.
+fuzz_target!(|data: &[u8]| {
+    let Some((split, data)) = data.split_first_chunk::<4>() else {
+        return
+    };
+    let split = usize::try_from(u32::from_le_bytes(*split)).unwrap_or(usize::MAX);
+    let (param, bin) = data.split_at(split.min(data.len()));
+    let _ = RealCugan::build()
+        .cpu()
+        .model_bytes(param, bin)
+        .build();
+});
@@ -3,6 +3,7 @@
 #include "realcugan.h"
 
 #include <algorithm>
+#include <cstring>
 #include <vector>
 #include <map>
 
@@ -18,18 +19,141 @@
 
 // CUSTOM
 
+// return codes forwarded to wrapper.cpp: -100 out of memory, -200 device lost, -300 inference failed
+static int extract_status(int ret)
+{
+    return ret == 0 ? 0 : ret == -100 ? -100 : -300;
+}
+
+// ncnn collapses every vkQueueSubmit / vkWaitForFences failure into -1
+static int submit_status(int ret)
+{
+    return ret == 0 ? 0 : -200;
+}
+
+#define RETURN_IF_FAILED(expr) \
+    do \
+    { \
+        int ret = (expr); \
+        if (ret != 0) \
+            return ret; \
+    } while (0)
+
+// reclaims the blob and staging allocators on every return path
+class AllocatorScope
+{
+public:
+    AllocatorScope(ncnn::VulkanDevice* vkdev) : vkdev(vkdev), blob(vkdev->acquire_blob_allocator()), staging(vkdev->acquire_staging_allocator())
+    {
+    }
+
+    ~AllocatorScope()
+    {
+        vkdev->reclaim_blob_allocator(blob);
+        vkdev->reclaim_staging_allocator(staging);
+    }
+
+    ncnn::VulkanDevice* vkdev;
+    ncnn::VkAllocator* blob;
+    ncnn::VkAllocator* staging;
+};
+
+static bool ncnn::Option::*find_option(const char *key)
+{
+    static const std::pair<const char *, bool ncnn::Option::*> fields[] = {
+        {"lightmode", &ncnn::Option::lightmode},
+        {"use_winograd_convolution", &ncnn::Option::use_winograd_convolution},
+        {"use_sgemm_convolution", &ncnn::Option::use_sgemm_convolution},
+        {"use_int8_inference", &ncnn::Option::use_int8_inference},
+        {"use_fp16_packed", &ncnn::Option::use_fp16_packed},
+        {"use_fp16_storage", &ncnn::Option::use_fp16_storage},
+        {"use_fp16_arithmetic", &ncnn::Option::use_fp16_arithmetic},
+        {"use_int8_packed", &ncnn::Option::use_int8_packed},
+        {"use_int8_storage", &ncnn::Option::use_int8_storage},
+        {"use_int8_arithmetic", &ncnn::Option::use_int8_arithmetic},
+        {"use_packing_layout", &ncnn::Option::use_packing_layout},
+        {"use_shader_pack8", &ncnn::Option::use_shader_pack8},
+        {"use_bf16_storage", &ncnn::Option::use_bf16_storage},
+    };
+    for (size_t i = 0; i < sizeof(fields) / sizeof(fields[0]); i++)
+    {
+        if (strcmp(fields[i].first, key) == 0)
+            return fields[i].second;
+    }
+    return 0;
+}
+
+// applied over the defaults and the precision when the model is loaded
+int RealCUGAN::set_option(const char *key, bool value)
+{
+    bool ncnn::Option::*field = find_option(key);
+    if (!field)
+        return -1;
+    options.push_back(std::make_pair(field, value));
+    return 0;
+}
+
+void RealCUGAN::unload()
+{
+    delete realcugan_preproc;
+    delete realcugan_postproc;
+    delete realcugan_4x_postproc;
+    realcugan_preproc = 0;
+    realcugan_postproc = 0;
+    realcugan_4x_postproc = 0;
+
+    ncnn::Layer* bicubic[3] = { bicubic_2x, bicubic_3x, bicubic_4x };
+    for (int i = 0; i < 3; i++)
+    {
+        if (bicubic[i])
+        {
+            bicubic[i]->destroy_pipeline(net.opt);
+            delete bicubic[i];
+        }
+    }
+    bicubic_2x = 0;
+    bicubic_3x = 0;
+    bicubic_4x = 0;
+
+    net.clear();
+}
+
 int RealCUGAN::load_files(FILE *param, FILE *bin)
 {
+    unload();
+
     net.opt.use_vulkan_compute = vkdev ? true : false;
     net.opt.use_fp16_packed = true;
     net.opt.use_fp16_storage = vkdev ? true : false;
     net.opt.use_fp16_arithmetic = false;
     net.opt.use_int8_storage = true;
 
+    // 0 keeps the defaults above, 1 adds fp16 arithmetic, 2 is fp32 only
+    if (precision == 1)
+    {
+        net.opt.use_fp16_arithmetic = vkdev ? true : false;
+    }
+    else if (precision == 2)
+    {
+        net.opt.use_fp16_packed = false;
+        net.opt.use_fp16_storage = false;
+    }
+
+    for (size_t i = 0; i < options.size(); i++)
+    {
+        net.opt.*(options[i].first) = options[i].second;
+    }
+
     net.set_vulkan_device(vkdev);
 
-    net.load_param(param);
-    net.load_model(bin);
+    // -2 invalid param, -3 bin smaller than the layers in param need,
+    // -4 bin larger than the layers in param need
+    if (net.load_param(param) != 0)
+        return -2;
+    if (net.load_model(bin) != 0)
+        return -3;
+    if (fgetc(bin) != EOF)
+        return -4;
 
     // initialize preprocess and postprocess pipeline
     if (vkdev)
@@ -182,7 +306,7 @@ public:
     std::map<std::string, ncnn::Mat> cpu_cache;
 };
 
-RealCUGAN::RealCUGAN(int gpuid, bool _tta_mode, int num_threads)
+RealCUGAN::RealCUGAN(int gpuid, bool _tta_mode, int num_threads, int _precision)
 {
     vkdev = gpuid == -1 ? 0 : ncnn::get_gpu_device(gpuid);
 
@@ -195,24 +319,12 @@ RealCUGAN::RealCUGAN(int gpuid, bool _tta_mode, int num_threads)
     bicubic_3x = 0;
     bicubic_4x = 0;
     tta_mode = _tta_mode;
+    precision = _precision;
 }
 
 RealCUGAN::~RealCUGAN()
 {
-    // cleanup preprocess and postprocess pipeline
-    {
-        delete realcugan_preproc;
-        delete realcugan_postproc;
-    }
-
-    bicubic_2x->destroy_pipeline(net.opt);
-    delete bicubic_2x;
-
-    bicubic_3x->destroy_pipeline(net.opt);
-    delete bicubic_3x;
-
-    bicubic_4x->destroy_pipeline(net.opt);
-    delete bicubic_4x;
+    unload();
 }
 
 int RealCUGAN::process(const ncnn::Mat& inimage, ncnn::Mat& outimage) const
@@ -259,8 +371,9 @@ int RealCUGAN::process(const ncnn::Mat& inimage, ncnn::Mat& outimage) const
     const int TILE_SIZE_X = tilesize;
     const int TILE_SIZE_Y = tilesize;
 
-    ncnn::VkAllocator* blob_vkallocator = vkdev->acquire_blob_allocator();
-    ncnn::VkAllocator* staging_vkallocator = vkdev->acquire_staging_allocator();
+    AllocatorScope allocators(vkdev);
+    ncnn::VkAllocator* blob_vkallocator = allocators.blob;
+    ncnn::VkAllocator* staging_vkallocator = allocators.staging;
 
     ncnn::Option opt = net.opt;
     opt.blob_vkallocator = blob_vkallocator;
@@ -325,7 +438,7 @@ int RealCUGAN::process(const ncnn::Mat& inimage, ncnn::Mat& outimage) const
 
             if (xtiles > 1)
             {
-                cmd.submit_and_wait();
+                RETURN_IF_FAILED(submit_status(cmd.submit_and_wait()));
                 cmd.reset();
             }
         }
@@ -430,7 +543,7 @@ int RealCUGAN::process(const ncnn::Mat& inimage, ncnn::Mat& outimage) const
 
                     ex.input("in0", in_tile_gpu[ti]);
 
-                    ex.extract("out0", out_tile_gpu[ti], cmd);
+                    RETURN_IF_FAILED(extract_status(ex.extract("out0", out_tile_gpu[ti], cmd)));
                 }
 
                 ncnn::VkMat out_alpha_tile_gpu;
@@ -442,15 +555,15 @@ int RealCUGAN::process(const ncnn::Mat& inimage, ncnn::Mat& outimage) const
                     }
                     if (scale == 2)
                     {
-                        bicubic_2x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_2x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt)));
                     }
                     if (scale == 3)
                     {
-                        bicubic_3x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_3x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt)));
                     }
                     if (scale == 4)
                     {
-                        bicubic_4x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_4x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt)));
                     }
                 }
 
@@ -588,7 +701,7 @@ int RealCUGAN::process(const ncnn::Mat& inimage, ncnn::Mat& outimage) const
 
                     ex.input("in0", in_tile_gpu);
 
-                    ex.extract("out0", out_tile_gpu, cmd);
+                    RETURN_IF_FAILED(extract_status(ex.extract("out0", out_tile_gpu, cmd)));
                 }
 
                 ncnn::VkMat out_alpha_tile_gpu;
@@ -600,15 +713,15 @@ int RealCUGAN::process(const ncnn::Mat& inimage, ncnn::Mat& outimage) const
                     }
                     if (scale == 2)
                     {
-                        bicubic_2x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_2x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt)));
                     }
                     if (scale == 3)
                     {
-                        bicubic_3x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_3x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt)));
                     }
                     if (scale == 4)
                     {
-                        bicubic_4x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_4x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt)));
                     }
                 }
 
@@ -677,7 +790,7 @@ int RealCUGAN::process(const ncnn::Mat& inimage, ncnn::Mat& outimage) const
 
             if (xtiles > 1)
             {
-                cmd.submit_and_wait();
+                RETURN_IF_FAILED(submit_status(cmd.submit_and_wait()));
                 cmd.reset();
             }
         }
@@ -693,7 +806,7 @@ int RealCUGAN::process(const ncnn::Mat& inimage, ncnn::Mat& outimage) const
 
             cmd.record_clone(out_gpu, out, opt);
 
-            cmd.submit_and_wait();
+            RETURN_IF_FAILED(submit_status(cmd.submit_and_wait()));
 
             if (!(opt.use_fp16_storage && opt.use_int8_storage))
             {
@@ -717,9 +830,6 @@ int RealCUGAN::process(const ncnn::Mat& inimage, ncnn::Mat& outimage) const
         }
     }
 
-    vkdev->reclaim_blob_allocator(blob_vkallocator);
-    vkdev->reclaim_staging_allocator(staging_vkallocator);
-
     return 0;
 }
 
@@ -898,7 +1008,7 @@ int RealCUGAN::process_cpu(const ncnn::Mat& inimage, ncnn::Mat& outimage) const
 
                     ex.input("in0", in_tile[ti]);
 
-                    ex.extract("out0", out_tile[ti]);
+                    RETURN_IF_FAILED(extract_status(ex.extract("out0", out_tile[ti])));
                 }
 
                 ncnn::Mat out_alpha_tile;
@@ -910,15 +1020,15 @@ int RealCUGAN::process_cpu(const ncnn::Mat& inimage, ncnn::Mat& outimage) const
                     }
                     if (scale == 2)
                     {
-                        bicubic_2x->forward(in_alpha_tile, out_alpha_tile, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_2x->forward(in_alpha_tile, out_alpha_tile, opt)));
                     }
                     if (scale == 3)
                     {
-                        bicubic_3x->forward(in_alpha_tile, out_alpha_tile, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_3x->forward(in_alpha_tile, out_alpha_tile, opt)));
                     }
                     if (scale == 4)
                     {
-                        bicubic_4x->forward(in_alpha_tile, out_alpha_tile, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_4x->forward(in_alpha_tile, out_alpha_tile, opt)));
                     }
                 }
 
@@ -1046,7 +1156,7 @@ int RealCUGAN::process_cpu(const ncnn::Mat& inimage, ncnn::Mat& outimage) const
 
                     ex.input("in0", in_tile);
 
-                    ex.extract("out0", out_tile);
+                    RETURN_IF_FAILED(extract_status(ex.extract("out0", out_tile)));
                 }
 
                 ncnn::Mat out_alpha_tile;
@@ -1058,15 +1168,15 @@ int RealCUGAN::process_cpu(const ncnn::Mat& inimage, ncnn::Mat& outimage) const
                     }
                     if (scale == 2)
                     {
-                        bicubic_2x->forward(in_alpha_tile, out_alpha_tile, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_2x->forward(in_alpha_tile, out_alpha_tile, opt)));
                     }
                     if (scale == 3)
                     {
-                        bicubic_3x->forward(in_alpha_tile, out_alpha_tile, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_3x->forward(in_alpha_tile, out_alpha_tile, opt)));
                     }
                     if (scale == 4)
                     {
-                        bicubic_4x->forward(in_alpha_tile, out_alpha_tile, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_4x->forward(in_alpha_tile, out_alpha_tile, opt)));
                     }
                 }
 
@@ -1142,8 +1252,9 @@ int RealCUGAN::process_cpu(const ncnn::Mat& inimage, ncnn::Mat& outimage) const
 
 int RealCUGAN::process_se(const ncnn::Mat& inimage, ncnn::Mat& outimage) const
 {
-    ncnn::VkAllocator* blob_vkallocator = vkdev->acquire_blob_allocator();
-    ncnn::VkAllocator* staging_vkallocator = vkdev->acquire_staging_allocator();
+    AllocatorScope allocators(vkdev);
+    ncnn::VkAllocator* blob_vkallocator = allocators.blob;
+    ncnn::VkAllocator* staging_vkallocator = allocators.staging;
 
     ncnn::Option opt = net.opt;
     opt.blob_vkallocator = blob_vkallocator;
@@ -1154,47 +1265,45 @@ int RealCUGAN::process_se(const ncnn::Mat& inimage, ncnn::Mat& outimage) const
 
     std::vector<std::string> in0 = {};
     std::vector<std::string> out0 = {"gap0"};
-    process_se_stage0(inimage, in0, out0, opt, cache);
+    RETURN_IF_FAILED(process_se_stage0(inimage, in0, out0, opt, cache));
 
     std::vector<std::string> gap0 = {"gap0"};
-    process_se_sync_gap(inimage, gap0, opt, cache);
+    RETURN_IF_FAILED(process_se_sync_gap(inimage, gap0, opt, cache));
 
     std::vector<std::string> in1 = {"gap0"};
     std::vector<std::string> out1 = {"gap1"};
-    process_se_stage0(inimage, in1, out1, opt, cache);
+    RETURN_IF_FAILED(process_se_stage0(inimage, in1, out1, opt, cache));
 
     std::vector<std::string> gap1 = {"gap1"};
-    process_se_sync_gap(inimage, gap1, opt, cache);
+    RETURN_IF_FAILED(process_se_sync_gap(inimage, gap1, opt, cache));
 
     std::vector<std::string> in2 = {"gap0", "gap1"};
     std::vector<std::string> out2 = {"gap2"};
-    process_se_stage0(inimage, in2, out2, opt, cache);
+    RETURN_IF_FAILED(process_se_stage0(inimage, in2, out2, opt, cache));
 
     std::vector<std::string> gap2 = {"gap2"};
-    process_se_sync_gap(inimage, gap2, opt, cache);
+    RETURN_IF_FAILED(process_se_sync_gap(inimage, gap2, opt, cache));
 
     std::vector<std::string> in3 = {"gap0", "gap1", "gap2"};
     std::vector<std::string> out3 = {"gap3"};
-    process_se_stage0(inimage, in3, out3, opt, cache);
+    RETURN_IF_FAILED(process_se_stage0(inimage, in3, out3, opt, cache));
 
     std::vector<std::string> gap3 = {"gap3"};
-    process_se_sync_gap(inimage, gap3, opt, cache);
+    RETURN_IF_FAILED(process_se_sync_gap(inimage, gap3, opt, cache));
 
     std::vector<std::string> in4 = {"gap0", "gap1", "gap2", "gap3"};
-    process_se_stage2(inimage, in4, outimage, opt, cache);
+    RETURN_IF_FAILED(process_se_stage2(inimage, in4, outimage, opt, cache));
 
     cache.clear();
 
-    vkdev->reclaim_blob_allocator(blob_vkallocator);
-    vkdev->reclaim_staging_allocator(staging_vkallocator);
-
     return 0;
 }
 
 int RealCUGAN::process_se_rough(const ncnn::Mat& inimage, ncnn::Mat& outimage) const
 {
-    ncnn::VkAllocator* blob_vkallocator = vkdev->acquire_blob_allocator();
-    ncnn::VkAllocator* staging_vkallocator = vkdev->acquire_staging_allocator();
+    AllocatorScope allocators(vkdev);
+    ncnn::VkAllocator* blob_vkallocator = allocators.blob;
+    ncnn::VkAllocator* staging_vkallocator = allocators.staging;
 
     ncnn::Option opt = net.opt;
     opt.blob_vkallocator = blob_vkallocator;
@@ -1205,26 +1314,24 @@ int RealCUGAN::process_se_rough(const ncnn::Mat& inimage, ncnn::Mat& outimage) c
 
     std::vector<std::string> in0 = {};
     std::vector<std::string> out0 = {"gap0", "gap1", "gap2", "gap3"};
-    process_se_stage0(inimage, in0, out0, opt, cache);
+    RETURN_IF_FAILED(process_se_stage0(inimage, in0, out0, opt, cache));
 
     std::vector<std::string> gap0 = {"gap0", "gap1", "gap2", "gap3"};
-    process_se_sync_gap(inimage, gap0, opt, cache);
+    RETURN_IF_FAILED(process_se_sync_gap(inimage, gap0, opt, cache));
 
     std::vector<std::string> in4 = {"gap0", "gap1", "gap2", "gap3"};
-    process_se_stage2(inimage, in4, outimage, opt, cache);
+    RETURN_IF_FAILED(process_se_stage2(inimage, in4, outimage, opt, cache));
 
     cache.clear();
 
-    vkdev->reclaim_blob_allocator(blob_vkallocator);
-    vkdev->reclaim_staging_allocator(staging_vkallocator);
-
     return 0;
 }
 
 int RealCUGAN::process_se_very_rough(const ncnn::Mat& inimage, ncnn::Mat& outimage) const
 {
-    ncnn::VkAllocator* blob_vkallocator = vkdev->acquire_blob_allocator();
-    ncnn::VkAllocator* staging_vkallocator = vkdev->acquire_staging_allocator();
+    AllocatorScope allocators(vkdev);
+    ncnn::VkAllocator* blob_vkallocator = allocators.blob;
+    ncnn::VkAllocator* staging_vkallocator = allocators.staging;
 
     ncnn::Option opt = net.opt;
     opt.blob_vkallocator = blob_vkallocator;
@@ -1235,19 +1342,16 @@ int RealCUGAN::process_se_very_rough(const ncnn::Mat& inimage, ncnn::Mat& outima
 
     std::vector<std::string> in0 = {};
     std::vector<std::string> out0 = {"gap0", "gap1", "gap2", "gap3"};
-    process_se_very_rough_stage0(inimage, in0, out0, opt, cache);
+    RETURN_IF_FAILED(process_se_very_rough_stage0(inimage, in0, out0, opt, cache));
 
     std::vector<std::string> gap0 = {"gap0", "gap1", "gap2", "gap3"};
-    process_se_very_rough_sync_gap(inimage, gap0, opt, cache);
+    RETURN_IF_FAILED(process_se_very_rough_sync_gap(inimage, gap0, opt, cache));
 
     std::vector<std::string> in4 = {"gap0", "gap1", "gap2", "gap3"};
-    process_se_stage2(inimage, in4, outimage, opt, cache);
+    RETURN_IF_FAILED(process_se_stage2(inimage, in4, outimage, opt, cache));
 
     cache.clear();
 
-    vkdev->reclaim_blob_allocator(blob_vkallocator);
-    vkdev->reclaim_staging_allocator(staging_vkallocator);
-
     return 0;
 }
 
@@ -1257,34 +1361,34 @@ int RealCUGAN::process_cpu_se(const ncnn::Mat& inimage, ncnn::Mat& outimage) con
 
     std::vector<std::string> in0 = {};
     std::vector<std::string> out0 = {"gap0"};
-    process_cpu_se_stage0(inimage, in0, out0, cache);
+    RETURN_IF_FAILED(process_cpu_se_stage0(inimage, in0, out0, cache));
 
     std::vector<std::string> gap0 = {"gap0"};
-    process_cpu_se_sync_gap(inimage, gap0, cache);
+    RETURN_IF_FAILED(process_cpu_se_sync_gap(inimage, gap0, cache));
 
     std::vector<std::string> in1 = {"gap0"};
     std::vector<std::string> out1 = {"gap1"};
-    process_cpu_se_stage0(inimage, in1, out1, cache);
+    RETURN_IF_FAILED(process_cpu_se_stage0(inimage, in1, out1, cache));
 
     std::vector<std::string> gap1 = {"gap1"};
-    process_cpu_se_sync_gap(inimage, gap1, cache);
+    RETURN_IF_FAILED(process_cpu_se_sync_gap(inimage, gap1, cache));
 
     std::vector<std::string> in2 = {"gap0", "gap1"};
     std::vector<std::string> out2 = {"gap2"};
-    process_cpu_se_stage0(inimage, in2, out2, cache);
+    RETURN_IF_FAILED(process_cpu_se_stage0(inimage, in2, out2, cache));
 
     std::vector<std::string> gap2 = {"gap2"};
-    process_cpu_se_sync_gap(inimage, gap2, cache);
+    RETURN_IF_FAILED(process_cpu_se_sync_gap(inimage, gap2, cache));
 
     std::vector<std::string> in3 = {"gap0", "gap1", "gap2"};
     std::vector<std::string> out3 = {"gap3"};
-    process_cpu_se_stage0(inimage, in3, out3, cache);
+    RETURN_IF_FAILED(process_cpu_se_stage0(inimage, in3, out3, cache));
 
     std::vector<std::string> gap3 = {"gap3"};
-    process_cpu_se_sync_gap(inimage, gap3, cache);
+    RETURN_IF_FAILED(process_cpu_se_sync_gap(inimage, gap3, cache));
 
     std::vector<std::string> in4 = {"gap0", "gap1", "gap2", "gap3"};
-    process_cpu_se_stage2(inimage, in4, outimage, cache);
+    RETURN_IF_FAILED(process_cpu_se_stage2(inimage, in4, outimage, cache));
 
     cache.clear();
 
@@ -1297,13 +1401,13 @@ int RealCUGAN::process_cpu_se_rough(const ncnn::Mat& inimage, ncnn::Mat& outimag
 
     std::vector<std::string> in0 = {};
     std::vector<std::string> out0 = {"gap0", "gap1", "gap2", "gap3"};
-    process_cpu_se_stage0(inimage, in0, out0, cache);
+    RETURN_IF_FAILED(process_cpu_se_stage0(inimage, in0, out0, cache));
 
     std::vector<std::string> gap0 = {"gap0", "gap1", "gap2", "gap3"};
-    process_cpu_se_sync_gap(inimage, gap0, cache);
+    RETURN_IF_FAILED(process_cpu_se_sync_gap(inimage, gap0, cache));
 
     std::vector<std::string> in4 = {"gap0", "gap1", "gap2", "gap3"};
-    process_cpu_se_stage2(inimage, in4, outimage, cache);
+    RETURN_IF_FAILED(process_cpu_se_stage2(inimage, in4, outimage, cache));
 
     cache.clear();
 
@@ -1316,13 +1420,13 @@ int RealCUGAN::process_cpu_se_very_rough(const ncnn::Mat& inimage, ncnn::Mat& ou
 
     std::vector<std::string> in0 = {};
     std::vector<std::string> out0 = {"gap0", "gap1", "gap2", "gap3"};
-    process_cpu_se_very_rough_stage0(inimage, in0, out0, cache);
+    RETURN_IF_FAILED(process_cpu_se_very_rough_stage0(inimage, in0, out0, cache));
 
     std::vector<std::string> gap0 = {"gap0", "gap1", "gap2", "gap3"};
-    process_cpu_se_very_rough_sync_gap(inimage, gap0, cache);
+    RETURN_IF_FAILED(process_cpu_se_very_rough_sync_gap(inimage, gap0, cache));
 
     std::vector<std::string> in4 = {"gap0", "gap1", "gap2", "gap3"};
-    process_cpu_se_stage2(inimage, in4, outimage, cache);
+    RETURN_IF_FAILED(process_cpu_se_stage2(inimage, in4, outimage, cache));
 
     cache.clear();
 
@@ -1397,7 +1501,7 @@ int RealCUGAN::process_se_stage0(const ncnn::Mat& inimage, const std::vector<std
 
             if (xtiles > 1)
             {
-                cmd.submit_and_wait();
+                RETURN_IF_FAILED(submit_status(cmd.submit_and_wait()));
                 cmd.reset();
             }
         }
@@ -1513,7 +1617,7 @@ int RealCUGAN::process_se_stage0(const ncnn::Mat& inimage, const std::vector<std
                     for (size_t i = 0; i < outnames.size(); i++)
                     {
                         ncnn::VkMat feat;
-                        ex.extract(outnames[i].c_str(), feat, cmd);
+                        RETURN_IF_FAILED(extract_status(ex.extract(outnames[i].c_str(), feat, cmd)));
 
                         cache.save(yi, xi, ti, outnames[i], feat);
                     }
@@ -1587,7 +1691,7 @@ int RealCUGAN::process_se_stage0(const ncnn::Mat& inimage, const std::vector<std
                     for (size_t i = 0; i < outnames.size(); i++)
                     {
                         ncnn::VkMat feat;
-                        ex.extract(outnames[i].c_str(), feat, cmd);
+                        RETURN_IF_FAILED(extract_status(ex.extract(outnames[i].c_str(), feat, cmd)));
 
                         cache.save(yi, xi, 0, outnames[i], feat);
                     }
@@ -1596,12 +1700,12 @@ int RealCUGAN::process_se_stage0(const ncnn::Mat& inimage, const std::vector<std
 
             if (xtiles > 1)
             {
-                cmd.submit_and_wait();
+                RETURN_IF_FAILED(submit_status(cmd.submit_and_wait()));
                 cmd.reset();
             }
         }
 
-        cmd.submit_and_wait();
+        RETURN_IF_FAILED(submit_status(cmd.submit_and_wait()));
         cmd.reset();
     }
 
@@ -1676,7 +1780,7 @@ int RealCUGAN::process_se_stage2(const ncnn::Mat& inimage, const std::vector<std
 
             if (xtiles > 1)
             {
-                cmd.submit_and_wait();
+                RETURN_IF_FAILED(submit_status(cmd.submit_and_wait()));
                 cmd.reset();
             }
         }
@@ -1789,7 +1893,7 @@ int RealCUGAN::process_se_stage2(const ncnn::Mat& inimage, const std::vector<std
                         ex.input(names[i].c_str(), feat);
                     }
 
-                    ex.extract("out0", out_tile_gpu[ti], cmd);
+                    RETURN_IF_FAILED(extract_status(ex.extract("out0", out_tile_gpu[ti], cmd)));
                 }
 
                 ncnn::VkMat out_alpha_tile_gpu;
@@ -1801,15 +1905,15 @@ int RealCUGAN::process_se_stage2(const ncnn::Mat& inimage, const std::vector<std
                     }
                     if (scale == 2)
                     {
-                        bicubic_2x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_2x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt)));
                     }
                     if (scale == 3)
                     {
-                        bicubic_3x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_3x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt)));
                     }
                     if (scale == 4)
                     {
-                        bicubic_4x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_4x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt)));
                     }
                 }
 
@@ -1955,7 +2059,7 @@ int RealCUGAN::process_se_stage2(const ncnn::Mat& inimage, const std::vector<std
                         ex.input(names[i].c_str(), feat);
                     }
 
-                    ex.extract("out0", out_tile_gpu, cmd);
+                    RETURN_IF_FAILED(extract_status(ex.extract("out0", out_tile_gpu, cmd)));
                 }
 
                 ncnn::VkMat out_alpha_tile_gpu;
@@ -1967,15 +2071,15 @@ int RealCUGAN::process_se_stage2(const ncnn::Mat& inimage, const std::vector<std
                     }
                     if (scale == 2)
                     {
-                        bicubic_2x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_2x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt)));
                     }
                     if (scale == 3)
                     {
-                        bicubic_3x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_3x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt)));
                     }
                     if (scale == 4)
                     {
-                        bicubic_4x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_4x->forward(in_alpha_tile_gpu, out_alpha_tile_gpu, cmd, opt)));
                     }
                 }
 
@@ -2044,7 +2148,7 @@ int RealCUGAN::process_se_stage2(const ncnn::Mat& inimage, const std::vector<std
 
             if (xtiles > 1)
             {
-                cmd.submit_and_wait();
+                RETURN_IF_FAILED(submit_status(cmd.submit_and_wait()));
                 cmd.reset();
             }
         }
@@ -2060,7 +2164,7 @@ int RealCUGAN::process_se_stage2(const ncnn::Mat& inimage, const std::vector<std
 
             cmd.record_clone(out_gpu, out, opt);
 
-            cmd.submit_and_wait();
+            RETURN_IF_FAILED(submit_status(cmd.submit_and_wait()));
 
             if (!(opt.use_fp16_storage && opt.use_int8_storage))
             {
@@ -2147,7 +2251,7 @@ int RealCUGAN::process_se_sync_gap(const ncnn::Mat& inimage, const std::vector<s
         }
     }
 
-    cmd.submit_and_wait();
+    RETURN_IF_FAILED(submit_status(cmd.submit_and_wait()));
     cmd.reset();
 
     // global average
@@ -2199,7 +2303,7 @@ int RealCUGAN::process_se_sync_gap(const ncnn::Mat& inimage, const std::vector<s
         }
     }
 
-    cmd.submit_and_wait();
+    RETURN_IF_FAILED(submit_status(cmd.submit_and_wait()));
     cmd.reset();
 
 
@@ -2297,7 +2401,7 @@ int RealCUGAN::process_se_very_rough_stage0(const ncnn::Mat& inimage, const std:
 
             if (xtiles > 1)
             {
-                cmd.submit_and_wait();
+                RETURN_IF_FAILED(submit_status(cmd.submit_and_wait()));
                 cmd.reset();
             }
         }
@@ -2413,7 +2517,7 @@ int RealCUGAN::process_se_very_rough_stage0(const ncnn::Mat& inimage, const std:
                     for (size_t i = 0; i < outnames.size(); i++)
                     {
                         ncnn::VkMat feat;
-                        ex.extract(outnames[i].c_str(), feat, cmd);
+                        RETURN_IF_FAILED(extract_status(ex.extract(outnames[i].c_str(), feat, cmd)));
 
                         cache.save(yi, xi, ti, outnames[i], feat);
                     }
@@ -2487,7 +2591,7 @@ int RealCUGAN::process_se_very_rough_stage0(const ncnn::Mat& inimage, const std:
                     for (size_t i = 0; i < outnames.size(); i++)
                     {
                         ncnn::VkMat feat;
-                        ex.extract(outnames[i].c_str(), feat, cmd);
+                        RETURN_IF_FAILED(extract_status(ex.extract(outnames[i].c_str(), feat, cmd)));
 
                         cache.save(yi, xi, 0, outnames[i], feat);
                     }
@@ -2496,12 +2600,12 @@ int RealCUGAN::process_se_very_rough_stage0(const ncnn::Mat& inimage, const std:
 
             if (xtiles > 1)
             {
-                cmd.submit_and_wait();
+                RETURN_IF_FAILED(submit_status(cmd.submit_and_wait()));
                 cmd.reset();
             }
         }
 
-        cmd.submit_and_wait();
+        RETURN_IF_FAILED(submit_status(cmd.submit_and_wait()));
         cmd.reset();
     }
 
@@ -2568,7 +2672,7 @@ int RealCUGAN::process_se_very_rough_sync_gap(const ncnn::Mat& inimage, const st
         }
     }
 
-    cmd.submit_and_wait();
+    RETURN_IF_FAILED(submit_status(cmd.submit_and_wait()));
     cmd.reset();
 
     // global average
@@ -2620,7 +2724,7 @@ int RealCUGAN::process_se_very_rough_sync_gap(const ncnn::Mat& inimage, const st
         }
     }
 
-    cmd.submit_and_wait();
+    RETURN_IF_FAILED(submit_status(cmd.submit_and_wait()));
     cmd.reset();
 
 
@@ -2846,7 +2950,7 @@ int RealCUGAN::process_cpu_se_stage0(const ncnn::Mat& inimage, const std::vector
                     for (size_t i = 0; i < outnames.size(); i++)
                     {
                         ncnn::Mat feat;
-                        ex.extract(outnames[i].c_str(), feat);
+                        RETURN_IF_FAILED(extract_status(ex.extract(outnames[i].c_str(), feat)));
 
                         cache.save(yi, xi, ti, outnames[i], feat);
                     }
@@ -2904,7 +3008,7 @@ int RealCUGAN::process_cpu_se_stage0(const ncnn::Mat& inimage, const std::vector
                     for (size_t i = 0; i < outnames.size(); i++)
                     {
                         ncnn::Mat feat;
-                        ex.extract(outnames[i].c_str(), feat);
+                        RETURN_IF_FAILED(extract_status(ex.extract(outnames[i].c_str(), feat)));
 
                         cache.save(yi, xi, 0, outnames[i], feat);
                     }
@@ -3093,7 +3197,7 @@ int RealCUGAN::process_cpu_se_stage2(const ncnn::Mat& inimage, const std::vector
                         ex.input(names[i].c_str(), feat);
                     }
 
-                    ex.extract("out0", out_tile[ti]);
+                    RETURN_IF_FAILED(extract_status(ex.extract("out0", out_tile[ti])));
                 }
 
                 ncnn::Mat out_alpha_tile;
@@ -3105,15 +3209,15 @@ int RealCUGAN::process_cpu_se_stage2(const ncnn::Mat& inimage, const std::vector
                     }
                     if (scale == 2)
                     {
-                        bicubic_2x->forward(in_alpha_tile, out_alpha_tile, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_2x->forward(in_alpha_tile, out_alpha_tile, opt)));
                     }
                     if (scale == 3)
                     {
-                        bicubic_3x->forward(in_alpha_tile, out_alpha_tile, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_3x->forward(in_alpha_tile, out_alpha_tile, opt)));
                     }
                     if (scale == 4)
                     {
-                        bicubic_4x->forward(in_alpha_tile, out_alpha_tile, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_4x->forward(in_alpha_tile, out_alpha_tile, opt)));
                     }
                 }
 
@@ -3249,7 +3353,7 @@ int RealCUGAN::process_cpu_se_stage2(const ncnn::Mat& inimage, const std::vector
                         ex.input(names[i].c_str(), feat);
                     }
 
-                    ex.extract("out0", out_tile);
+                    RETURN_IF_FAILED(extract_status(ex.extract("out0", out_tile)));
                 }
 
                 ncnn::Mat out_alpha_tile;
@@ -3261,15 +3365,15 @@ int RealCUGAN::process_cpu_se_stage2(const ncnn::Mat& inimage, const std::vector
                     }
                     if (scale == 2)
                     {
-                        bicubic_2x->forward(in_alpha_tile, out_alpha_tile, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_2x->forward(in_alpha_tile, out_alpha_tile, opt)));
                     }
                     if (scale == 3)
                     {
-                        bicubic_3x->forward(in_alpha_tile, out_alpha_tile, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_3x->forward(in_alpha_tile, out_alpha_tile, opt)));
                     }
                     if (scale == 4)
                     {
-                        bicubic_4x->forward(in_alpha_tile, out_alpha_tile, opt);
+                        RETURN_IF_FAILED(extract_status(bicubic_4x->forward(in_alpha_tile, out_alpha_tile, opt)));
                     }
                 }
 
@@ -3628,7 +3732,7 @@ int RealCUGAN::process_cpu_se_very_rough_stage0(const ncnn::Mat& inimage, const
                     for (size_t i = 0; i < outnames.size(); i++)
                     {
                         ncnn::Mat feat;
-                        ex.extract(outnames[i].c_str(), feat);
+                        RETURN_IF_FAILED(extract_status(ex.extract(outnames[i].c_str(), feat)));
 
                         cache.save(yi, xi, ti, outnames[i], feat);
                     }
@@ -3686,7 +3790,7 @@ int RealCUGAN::process_cpu_se_very_rough_stage0(const ncnn::Mat& inimage, const
                     for (size_t i = 0; i < outnames.size(); i++)
                     {
                         ncnn::Mat feat;
-                        ex.extract(outnames[i].c_str(), feat);
+                        RETURN_IF_FAILED(extract_status(ex.extract(outnames[i].c_str(), feat)));
 
                         cache.save(yi, xi, 0, outnames[i], feat);
                     }
@@ -4,6 +4,8 @@
 #define REALCUGAN_H
 
 #include <string>
+#include <utility>
+#include <vector>
 
 // ncnn
 #include "net.h"
@@ -14,11 +16,16 @@ class FeatureCache;
 class RealCUGAN
 {
 public:
-    RealCUGAN(int gpuid, bool tta_mode = false, int num_threads = 1);
+    RealCUGAN(int gpuid, bool tta_mode = false, int num_threads = 1, int precision = 0);
     ~RealCUGAN();
 
     int load_files(FILE *param, FILE *bin);
 
+    // releases the network and pipelines so load_files can run again
+    void unload();
+
+    int set_option(const char *key, bool value);
+
     int process(const ncnn::Mat& inimage, ncnn::Mat& outimage) const;
 
     int process_cpu(const ncnn::Mat& inimage, ncnn::Mat& outimage) const;
@@ -68,6 +75,8 @@ private:
     ncnn::Layer* bicubic_3x;
     ncnn::Layer* bicubic_4x;
     bool tta_mode;
+    int precision;
+    std::vector<std::pair<bool ncnn::Option::*, bool> > options;
 };
 
 #endif // REALCUGAN_H
@@ -7,6 +7,10 @@
 #include "realcugan.h"
 
 #include <algorithm>
+#include <atomic>
+#include <cstdarg>
+#include <cstdio>
+#include <cstring>
 #include <vector>
 #include <map>
 
@@ -27,8 +31,47 @@ typedef struct Image {
   int c;
 } Image;
 
-extern "C" RealCUGAN *realcugan_init(int gpuid, bool tta_mode, int num_threads) {
-  return new RealCUGAN(gpuid, tta_mode, num_threads);
+typedef struct GpuInfo {
+  char name[256];
+  uint32_t vendor_id;
+  uint32_t device_id;
+  uint32_t vram_mb;
+} GpuInfo;
+
+// return codes of realcugan_process and realcugan_process_cpu:
+//   0    success
+//   -1   invalid input image
+//   -100 failed to allocate the output image, or ncnn ran out of host or device memory
+//   -200 submitting to or waiting on the gpu failed, usually because the device was lost
+//   -300 a layer of the network failed for any other reason
+static bool is_valid_image(const Image *image) {
+  return image->data != nullptr && image->w > 0 && image->h > 0 && image->c >= 1 && image->c <= 4;
+}
+
+typedef void (*realcugan_log_callback)(const char *message);
+
+static std::atomic<realcugan_log_callback> log_callback{nullptr};
+
+extern "C" void realcugan_set_log_callback(realcugan_log_callback callback) {
+  log_callback.store(callback);
+}
+
+// NCNN_LOGE expands to this when ncnn is built with the ncnn-logs feature
+extern "C" void realcugan_ncnn_logf(const char *format, ...) {
+  realcugan_log_callback callback = log_callback.load();
+  if (callback == nullptr) {
+    return;
+  }
+  char message[1024];
+  va_list args;
+  va_start(args, format);
+  vsnprintf(message, sizeof(message), format, args);
+  va_end(args);
+  callback(message);
+}
+
+extern "C" RealCUGAN *realcugan_init(int gpuid, bool tta_mode, int num_threads, int precision) {
+  return new RealCUGAN(gpuid, tta_mode, num_threads, precision);
 }
 
 extern "C" int realcugan_get_gpu_count() {
@@ -47,6 +90,10 @@ extern "C" int realcugan_load_files(
   return realcugan->load_files(param, bin);
 }
 
+extern "C" int realcugan_set_option(RealCUGAN *realcugan, const char *key, bool value) {
+  return realcugan->set_option(key, value);
+}
+
 extern "C" void realcugan_set_parameters(
   RealCUGAN *realcugan,
   int scale,
@@ -68,9 +115,16 @@ extern "C" int realcugan_process(
   Image *out_image,
   void **mat_ptr
 ) {
+  if (!is_valid_image(in_image)) {
+    return -1;
+  }
   int c = in_image->c;
   ncnn::Mat in_image_mat = ncnn::Mat(in_image->w, in_image->h, (void *)in_image->data, (size_t)c, c);
   auto *out_image_mat = new ncnn::Mat(out_image->w, out_image->h, (size_t)c, c);
+  if (out_image_mat->empty()) {
+    delete out_image_mat;
+    return -100;
+  }
 
   int result = realcugan->process(in_image_mat, *out_image_mat);
   out_image->data = static_cast<unsigned char *>(out_image_mat->data);
@@ -84,10 +138,17 @@ extern "C" int realcugan_process_cpu(
   Image *out_image,
   void **mat_ptr
 ) {
+  if (!is_valid_image(in_image)) {
+    return -1;
+  }
   int c = in_image->c;
   ncnn::Mat in_image_mat =
       ncnn::Mat(in_image->w, in_image->h, (void *)in_image->data, (size_t)c, c);
   auto *out_image_mat = new ncnn::Mat(out_image->w, out_image->h, (size_t)c, c);
+  if (out_image_mat->empty()) {
+    delete out_image_mat;
+    return -100;
+  }
 
   int result = realcugan->process_cpu(in_image_mat, *out_image_mat);
   out_image->data = static_cast<unsigned char *>(out_image_mat->data);
@@ -99,6 +160,27 @@ extern "C" uint32_t realcugan_get_heap_budget(int gpuid) {
   return ncnn::get_gpu_device(gpuid)->get_heap_budget();
 }
 
+extern "C" int realcugan_get_gpu_info(int gpuid, GpuInfo *info) {
+  if (gpuid < 0 || gpuid >= ncnn::get_gpu_count()) {
+    return -1;
+  }
+  const ncnn::GpuInfo &gpu_info = ncnn::get_gpu_info(gpuid);
+  strncpy(info->name, gpu_info.device_name(), sizeof(info->name) - 1);
+  info->name[sizeof(info->name) - 1] = '\0';
+  info->vendor_id = gpu_info.vendor_id();
+  info->device_id = gpu_info.device_id();
+
+  const VkPhysicalDeviceMemoryProperties &properties = gpu_info.physical_device_memory_properties();
+  uint64_t vram = 0;
+  for (uint32_t i = 0; i < properties.memoryHeapCount; i++) {
+    if (properties.memoryHeaps[i].flags & VK_MEMORY_HEAP_DEVICE_LOCAL_BIT) {
+      vram += properties.memoryHeaps[i].size;
+    }
+  }
+  info->vram_mb = (uint32_t)(vram / (1024 * 1024));
+  return 0;
+}
+
 extern "C" void realcugan_free_image(ncnn::Mat *mat_ptr) {
   delete mat_ptr;
 }
@@ -0,0 +1,32 @@
+use std::time::Duration;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BenchResult {
+    pub iterations: u32,
+    pub min: Duration,
+    pub max: Duration,
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p99: Duration,
+}
+
+// Nearest-rank percentile of sorted samples.
+fn percentile(samples: &[Duration], percent: usize) -> Duration {
+    let rank = (samples.len() * percent).div_ceil(100).max(1);
+    samples[rank - 1]
+}
+
+impl BenchResult {
+    pub(crate) fn from_samples(mut samples: Vec<Duration>) -> Self {
+        samples.sort();
+        let total: Duration = samples.iter().sum();
+        Self {
+            iterations: samples.len() as u32,
+            min: samples[0],
+            max: samples[samples.len() - 1],
+            mean: total / samples.len() as u32,
+            p50: percentile(&samples, 50),
+            p99: percentile(&samples, 99),
+        }
+    }
+}
@@ -1,7 +1,15 @@
-use crate::realcugan::RealCugan;
+use crate::error::RealCuganError;
+use crate::realcugan::{NativeOptions, RealCugan};
+#[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+use crate::realcugan::AutoNoise;
+use crate::tile::TileHook;
+
+use std::time::Duration;
+
+use image::imageops::FilterType;
 
 #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Model {
     #[cfg(feature = "models-se")]
     Se2xNoDenoise,
@@ -41,21 +49,175 @@ pub enum Model {
     Nose2xNoDenoise
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+impl Model {
+    fn parameters(&self) -> ModelParameters<'static> {
+        match self {
+            #[cfg(feature = "models-nose")]
+            Model::Nose2xNoDenoise => MODEL_NOSE_2X_NO_DENOISE,
+            #[cfg(feature = "models-pro")]
+            Model::Pro2xNoDenoise => MODEL_PRO_2X_NO_DENOISE,
+            #[cfg(feature = "models-pro")]
+            Model::Pro2XConservative => MODEL_PRO_2X_CONSERVATIVE,
+            #[cfg(feature = "models-pro")]
+            Model::Pro2XHighDenoise => MODEL_PRO_2X_DENOISE_X3,
+            #[cfg(feature = "models-pro")]
+            Model::Pro3xNoDenoise => MODEL_PRO_3X_NO_DENOISE,
+            #[cfg(feature = "models-pro")]
+            Model::Pro3XConservative => MODEL_PRO_3X_CONSERVATIVE,
+            #[cfg(feature = "models-pro")]
+            Model::Pro3XHighDenoise => MODEL_PRO_3X_DENOISE_X3,
+            #[cfg(feature = "models-se")]
+            Model::Se2xNoDenoise => MODEL_SE_2X_NO_DENOISE,
+            #[cfg(feature = "models-se")]
+            Model::Se2xConservative => MODEL_SE_2X_CONSERVATIVE,
+            #[cfg(feature = "models-se")]
+            Model::Se2xLowDenoise => MODEL_SE_2X_DENOISE_X1,
+            #[cfg(feature = "models-se")]
+            Model::Se2xMediumDenoise => MODEL_SE_2X_DENOISE_X2,
+            #[cfg(feature = "models-se")]
+            Model::Se2xHighDenoise => MODEL_SE_2X_DENOISE_X3,
+            #[cfg(feature = "models-se")]
+            Model::Se3xNoDenoise => MODEL_SE_3X_NO_DENOISE,
+            #[cfg(feature = "models-se")]
+            Model::Se3xConservative => MODEL_SE_3X_CONSERVATIVE,
+            #[cfg(feature = "models-se")]
+            Model::Se3xHighDenoise => MODEL_SE_3X_DENOISE_X3,
+            #[cfg(feature = "models-se")]
+            Model::Se4xNoDenoise => MODEL_SE_4X_NO_DENOISE,
+            #[cfg(feature = "models-se")]
+            Model::Se4xConservative => MODEL_SE_4X_CONSERVATIVE,
+            #[cfg(feature = "models-se")]
+            Model::Se4xHighDenoise => MODEL_SE_4X_DENOISE_X3,
+        }
+    }
+
+    pub fn scale(&self) -> i32 {
+        self.parameters().scale
+    }
+
+    pub fn noise(&self) -> i32 {
+        self.parameters().noise
+    }
+
+    fn family(&self) -> &'static [Model] {
+        #[cfg(feature = "models-se")]
+        {
+            const SE: &[Model] = &[
+                Model::Se2xNoDenoise, Model::Se2xConservative, Model::Se2xLowDenoise, Model::Se2xMediumDenoise,
+                Model::Se2xHighDenoise, Model::Se3xNoDenoise, Model::Se3xConservative, Model::Se3xHighDenoise,
+                Model::Se4xNoDenoise, Model::Se4xConservative, Model::Se4xHighDenoise,
+            ];
+            if SE.contains(self) {
+                return SE
+            }
+        }
+        #[cfg(feature = "models-pro")]
+        {
+            const PRO: &[Model] = &[
+                Model::Pro2xNoDenoise, Model::Pro2XConservative, Model::Pro2XHighDenoise,
+                Model::Pro3xNoDenoise, Model::Pro3XConservative, Model::Pro3XHighDenoise,
+            ];
+            if PRO.contains(self) {
+                return PRO
+            }
+        }
+        #[cfg(feature = "models-nose")]
+        {
+            const NOSE: &[Model] = &[Model::Nose2xNoDenoise];
+            if NOSE.contains(self) {
+                return NOSE
+            }
+        }
+        &[]
+    }
+
+    pub fn with_noise(&self, noise: i32) -> Option<Model> {
+        self.family()
+            .iter()
+            .copied()
+            .find(|model| model.scale() == self.scale() && model.noise() == noise)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
 pub enum SyncGap {
     Disabled,      // 0
     Loose,         // 1
     Moderate,      // 2
+    #[default]
     Strict,        // 3 (default)
 }
 
+impl SyncGap {
+    pub fn as_i32(&self) -> i32 {
+        match self {
+            SyncGap::Disabled => 0,
+            SyncGap::Loose => 1,
+            SyncGap::Moderate => 2,
+            SyncGap::Strict => 3,
+        }
+    }
+
+    pub(crate) fn from_i32(gap: i32) -> Self {
+        match gap {
+            1 => SyncGap::Loose,
+            2 => SyncGap::Moderate,
+            3 => SyncGap::Strict,
+            _ => SyncGap::Disabled,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum TileStrategy {
+    #[default]
+    Auto,        // picked once from the heap budget at build time
+    Fixed(u32),
+    None,        // the whole image as one tile
+    Adaptive,    // picked per image from its size and the current heap budget
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Precision {
+    Fp16,  // fp16 storage and arithmetic
+    Fp32,  // fp32 storage and arithmetic
+}
+
+impl Precision {
+    pub(crate) fn as_i32(&self) -> i32 {
+        match self {
+            Precision::Fp16 => 1,
+            Precision::Fp32 => 2,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 struct GeneralParameters {
     gpu: i32,
-    tile_size: i32,
+    tile_strategy: TileStrategy,
     sync_gap: i32,
     threads: i32,
     tta: bool,
+    keep_input_channels: bool,
+    match_input_type: bool,
+    auto_retry_oom: bool,
+    deterministic: bool,
+    single_threaded: bool,
+    linear_light: bool,
+    premultiplied_alpha: bool,
+    tile_overlap: u32,
+    output_bit_depth: Option<u8>,
+    jpeg_quality: Option<u8>,
+    max_in_flight: Option<usize>,
+    timeout: Option<Duration>,
+    precision: Option<Precision>,
+    cpu_fallback: bool,
+    ncnn_options: Vec<(String, String)>,
+    tile_hook: Option<TileHook>,
+    #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+    auto_noise: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -70,6 +232,11 @@ struct ModelParameters<'a> {
 #[derive(Debug, Clone)]
 pub struct Builder<'a> {
     files: Option<(&'a str, &'a str)>,
+    blob: Option<&'a [u8]>,
+    owned: Option<(Vec<u8>, Vec<u8>)>,
+    #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+    model: Option<Model>,
+    passthrough: Option<FilterType>,
     parameters: GeneralParameters,
     model_parameters: ModelParameters<'a>
 }
@@ -78,12 +245,35 @@ impl <'a>Default for Builder<'a> {
     fn default() -> Self {
         Self {
             files: None,
+            blob: None,
+            owned: None,
+            #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+            model: None,
+            passthrough: None,
             parameters: GeneralParameters{
                 gpu: 0,
-                tile_size: 0,
-                sync_gap: 3,
+                tile_strategy: TileStrategy::Auto,
+                sync_gap: SyncGap::default().as_i32(),
                 tta: false,
                 threads: 1,
+                keep_input_channels: false,
+                match_input_type: false,
+                auto_retry_oom: false,
+                deterministic: false,
+                single_threaded: false,
+                linear_light: false,
+                premultiplied_alpha: false,
+                tile_overlap: 0,
+                output_bit_depth: None,
+                jpeg_quality: None,
+                max_in_flight: None,
+                timeout: None,
+                precision: None,
+                cpu_fallback: false,
+                ncnn_options: Vec::new(),
+                tile_hook: None,
+                #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+                auto_noise: false,
             },
             model_parameters: ModelParameters {
                 param: &[],
@@ -102,33 +292,134 @@ impl <'a>Builder<'a> {
         Self::default()
     }
 
+    pub fn with<F: FnOnce(Self) -> Self>(self, f: F) -> Self {
+        f(self)
+    }
+
     pub fn gpu(mut self, gpu: u32) -> Self {
         self.parameters.gpu = gpu as i32;
         self
     }
 
+    pub fn gpu_with_cpu_fallback(mut self, gpu: u32) -> Self {
+        self.parameters.gpu = gpu as i32;
+        self.parameters.cpu_fallback = true;
+        self
+    }
+
     pub fn cpu(mut self) -> Self {
         self.parameters.gpu = -1;
         self
     }
 
+    pub fn cpu_single_threaded(mut self) -> Self {
+        self.parameters.gpu = -1;
+        self.parameters.single_threaded = true;
+        self
+    }
+
     pub fn tta(mut self) -> Self {
         self.parameters.tta = true;
         self
     }
 
+    pub fn keep_input_channels(mut self) -> Self {
+        self.parameters.keep_input_channels = true;
+        self
+    }
+
+    pub fn match_input_type(mut self) -> Self {
+        self.parameters.match_input_type = true;
+        self
+    }
+
+    pub fn auto_retry_oom(mut self, retry: bool) -> Self {
+        self.parameters.auto_retry_oom = retry;
+        self
+    }
+
+    pub fn deterministic(mut self) -> Self {
+        self.parameters.deterministic = true;
+        self
+    }
+
+    pub fn linear_light(mut self) -> Self {
+        self.parameters.linear_light = true;
+        self
+    }
+
+    pub fn premultiplied_alpha(mut self, premultiplied: bool) -> Self {
+        self.parameters.premultiplied_alpha = premultiplied;
+        self
+    }
+
+    pub fn no_tiling(mut self) -> Self {
+        self.parameters.tile_strategy = TileStrategy::None;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.parameters.timeout = Some(timeout);
+        self
+    }
+
+    pub fn output_bit_depth(mut self, depth: u8) -> Self {
+        self.parameters.output_bit_depth = Some(depth);
+        self
+    }
+
+    pub fn jpeg_quality(mut self, quality: u8) -> Self {
+        self.parameters.jpeg_quality = Some(quality);
+        self
+    }
+
+    pub fn max_in_flight(mut self, images: usize) -> Self {
+        self.parameters.max_in_flight = Some(images);
+        self
+    }
+
+    pub fn tile_overlap(mut self, overlap: u32) -> Self {
+        self.parameters.tile_overlap = overlap;
+        self
+    }
+
+    pub fn tile_hook(mut self, hook: impl Fn(&mut [u8], u32, u32) + Send + Sync + 'static) -> Self {
+        self.parameters.tile_hook = Some(TileHook::new(hook));
+        self
+    }
+
     pub fn tile_size(mut self, tile_size: u32) -> Self {
-        self.parameters.tile_size = tile_size as i32;
+        self.parameters.tile_strategy = match tile_size {
+            0 => TileStrategy::Auto,
+            size => TileStrategy::Fixed(size),
+        };
+        self
+    }
+
+    pub fn tile_strategy(mut self, strategy: TileStrategy) -> Self {
+        self.parameters.tile_strategy = strategy;
         self
     }
 
+    fn fixed_tile_size(&self) -> i32 {
+        match self.parameters.tile_strategy {
+            TileStrategy::Fixed(size) => size as i32,
+            _ => 0,
+        }
+    }
+
     pub fn sync_gap(mut self, sync_gap: SyncGap) -> Self {
-        self.parameters.sync_gap = match sync_gap {
-            SyncGap::Disabled => 0,
-            SyncGap::Loose => 1,
-            SyncGap::Moderate => 2,
-            SyncGap::Strict => 3,
-        };
+        self.parameters.sync_gap = sync_gap.as_i32();
+        self
+    }
+
+    pub fn precision(mut self, precision: Precision) -> Self {
+        self.parameters.precision = Some(precision);
+        self
+    }
+
+    pub fn ncnn_option(mut self, key: &str, value: &str) -> Self {
+        self.parameters.ncnn_options.push((key.to_string(), value.to_string()));
         self
     }
 
@@ -149,6 +440,9 @@ impl <'a>Builder<'a> {
 
     pub fn model_files(mut self, param_file: &'a str, bin_file: &'a str) -> Self {
         self.files = Some((param_file, bin_file));
+        self.blob = None;
+        self.owned = None;
+        self.clear_model();
         self
     }
 
@@ -156,90 +450,206 @@ impl <'a>Builder<'a> {
         self.model_parameters.param = param;
         self.model_parameters.bin = bin;
         self.files = None;
+        self.blob = None;
+        self.owned = None;
+        self.clear_model();
+        self
+    }
+
+    pub fn model_bytes_owned(mut self, param: Vec<u8>, bin: Vec<u8>) -> Self {
+        self.owned = Some((param, bin));
+        self.files = None;
+        self.blob = None;
+        self.clear_model();
         self
     }
 
     #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
     pub fn model(mut self, model: Model) -> Self {
-        let model = match model {
-            #[cfg(feature = "models-nose")]
-            Model::Nose2xNoDenoise => MODEL_NOSE_2X_NO_DENOISE,
-            #[cfg(feature = "models-pro")]
-            Model::Pro2xNoDenoise => MODEL_PRO_2X_NO_DENOISE,
-            #[cfg(feature = "models-pro")]
-            Model::Pro2XConservative => MODEL_PRO_2X_CONSERVATIVE,
-            #[cfg(feature = "models-pro")]
-            Model::Pro2XHighDenoise => MODEL_PRO_2X_DENOISE_X3,
-            #[cfg(feature = "models-pro")]
-            Model::Pro3xNoDenoise => MODEL_PRO_3X_NO_DENOISE,
-            #[cfg(feature = "models-pro")]
-            Model::Pro3XConservative => MODEL_PRO_3X_CONSERVATIVE,
-            #[cfg(feature = "models-pro")]
-            Model::Pro3XHighDenoise => MODEL_PRO_3X_DENOISE_X3,
-            #[cfg(feature = "models-se")]
-            Model::Se2xNoDenoise => MODEL_SE_2X_NO_DENOISE,
-            #[cfg(feature = "models-se")]
-            Model::Se2xConservative => MODEL_SE_2X_CONSERVATIVE,
-            #[cfg(feature = "models-se")]
-            Model::Se2xLowDenoise => MODEL_SE_2X_DENOISE_X1,
-            #[cfg(feature = "models-se")]
-            Model::Se2xMediumDenoise => MODEL_SE_2X_DENOISE_X2,
-            #[cfg(feature = "models-se")]
-            Model::Se2xHighDenoise => MODEL_SE_2X_DENOISE_X3,
-            #[cfg(feature = "models-se")]
-            Model::Se3xNoDenoise => MODEL_SE_3X_NO_DENOISE,
-            #[cfg(feature = "models-se")]
-            Model::Se3xConservative => MODEL_SE_3X_CONSERVATIVE,
-            #[cfg(feature = "models-se")]
-            Model::Se3xHighDenoise => MODEL_SE_3X_DENOISE_X3,
-            #[cfg(feature = "models-se")]
-            Model::Se4xNoDenoise => MODEL_SE_4X_NO_DENOISE,
-            #[cfg(feature = "models-se")]
-            Model::Se4xConservative => MODEL_SE_4X_CONSERVATIVE,
-            #[cfg(feature = "models-se")]
-            Model::Se4xHighDenoise => MODEL_SE_4X_DENOISE_X3,
-        };
         self.files = None;
-        self.model_parameters = model;
+        self.blob = None;
+        self.owned = None;
+        self.model = Some(model);
+        self.model_parameters = model.parameters();
+        self
+    }
+
+    pub fn model_blob(mut self, blob: &'a [u8]) -> Self {
+        self.blob = Some(blob);
+        self.files = None;
+        self.owned = None;
+        self.clear_model();
         self
     }
 
-    fn get_bytes(&self) -> Result<(Vec<u8>, Vec<u8>), String> {
+    fn clear_model(&mut self) {
+        #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+        {
+            self.model = None;
+        }
+    }
+
+    // Picks the built-in model of the same family and scale for the noise
+    // estimated on each image. Only built-in models can be switched, so
+    // building with other model data fails with AutoNoiseWithoutModel.
+    #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+    pub fn auto_noise(mut self) -> Self {
+        self.parameters.auto_noise = true;
+        self
+    }
+
+    // The builder for the other models of the family, with the same
+    // settings and without auto_noise so those instances keep their model.
+    #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+    fn for_model(&self, model: Model) -> Builder<'static> {
+        Builder {
+            files: None,
+            blob: None,
+            owned: None,
+            model: Some(model),
+            passthrough: self.passthrough,
+            parameters: GeneralParameters { auto_noise: false, ..self.parameters.clone() },
+            model_parameters: model.parameters(),
+        }
+    }
+
+    pub fn passthrough_resize(mut self, filter: FilterType) -> Self {
+        self.passthrough = Some(filter);
+        self
+    }
+
+    pub fn estimate_vram(&self, input_w: u32, input_h: u32) -> u64 {
+        if self.passthrough.is_some() {
+            return 0
+        }
+        let tile_size = if self.parameters.tile_strategy == TileStrategy::None {
+            input_w.max(input_h) as i32
+        } else {
+            self.fixed_tile_size()
+        };
+        RealCugan::estimate_vram(tile_size, self.model_parameters.scale, input_w, input_h)
+    }
+
+    fn get_bytes(&self) -> Result<(Vec<u8>, Vec<u8>), RealCuganError> {
         if let Some((param_file, bin_file)) = &self.files {
             let param = std::fs::read(param_file)
-                .map_err(|e| format!("failed to read param file: {}", e))?;
+                .map_err(|e| RealCuganError::Io(format!("failed to read param file: {}", e)))?;
             let bin = std::fs::read(bin_file)
-                .map_err(|e| format!("failed to read bin file: {}", e))?;
+                .map_err(|e| RealCuganError::Io(format!("failed to read bin file: {}", e)))?;
             Ok((param, bin))
+        } else if let Some(blob) = self.blob {
+            let (param, bin) = RealCugan::unpack_model(blob)?;
+            Ok((param.to_vec(), bin.to_vec()))
+        } else if let Some((param, bin)) = &self.owned {
+            Ok((param.clone(), bin.clone()))
         } else {
             Ok((self.model_parameters.param.to_vec(), self.model_parameters.bin.to_vec()))
         }
     }
 
-    pub fn build(&self) -> Result<RealCugan, String> {
+    pub fn build(&self) -> Result<RealCugan, RealCuganError> {
+
+        if let Some(depth) = self.parameters.output_bit_depth.filter(|depth| *depth != 8 && *depth != 16) {
+            return Err(RealCuganError::InvalidBitDepth(depth))
+        }
+        if let Some(quality) = self.parameters.jpeg_quality.filter(|quality| !(1..=100).contains(quality)) {
+            return Err(RealCuganError::InvalidJpegQuality(quality))
+        }
+        if let TileStrategy::Fixed(size) = self.parameters.tile_strategy {
+            if self.parameters.tile_overlap > 0 && self.parameters.tile_overlap >= size / 2 {
+                return Err(RealCuganError::InvalidParameter(format!(
+                    "tile overlap {} has to be less than half the tile size {}", self.parameters.tile_overlap, size
+                )))
+            }
+        }
+        #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+        if self.parameters.auto_noise && self.model.is_none() {
+            return Err(RealCuganError::AutoNoiseWithoutModel)
+        }
+
+        if let Some(filter) = self.passthrough {
+            return RealCugan::passthrough(self.model_parameters.scale, filter)
+        }
 
         let (param, bin) = self.get_bytes()?;
 
-        let sync_gap = if self.model_parameters.allow_sync_gap { 
+        let sync_gap = if self.model_parameters.allow_sync_gap && !self.parameters.deterministic {
             self.parameters.sync_gap
         } else {
             0
         };
-        RealCugan::new(
-            self.parameters.gpu,
-            self.parameters.threads,
+        let threads = if self.parameters.deterministic || self.parameters.single_threaded {
+            1
+        } else {
+            self.parameters.threads
+        };
+        let options = NativeOptions {
+            precision: self.parameters.precision,
+            ncnn: self.parameters.ncnn_options.clone(),
+        };
+        let create = |gpu| RealCugan::with_options(
+            gpu,
+            threads,
             self.parameters.tta,
             sync_gap,
-            self.parameters.tile_size,
+            self.fixed_tile_size(),
             self.model_parameters.scale,
             self.model_parameters.noise,
+            &options,
             &param,
             &bin
+        );
+        // ncnn does not tell a model it cannot set up on the device apart from
+        // a broken one, so only missing devices fall back
+        let result = match create(self.parameters.gpu) {
+            Err(RealCuganError::GpuNotFound { .. } | RealCuganError::NoGpuAvailable)
+                if self.parameters.cpu_fallback && self.parameters.gpu != -1 => create(-1),
+            result => result,
+        };
+        result.map(|realcugan| self.with_auto_noise(realcugan)
+            .keep_input_channels(self.parameters.keep_input_channels)
+            .match_input_type(self.parameters.match_input_type)
+            .auto_retry_oom(self.parameters.auto_retry_oom)
+            .allow_sync_gap(self.model_parameters.allow_sync_gap)
+            .linear_light(self.parameters.linear_light)
+            .premultiplied_alpha(self.parameters.premultiplied_alpha)
+            .tile_strategy(self.parameters.tile_strategy)
+            .tile_hook(self.parameters.tile_hook.clone())
+            .tile_overlap(self.parameters.tile_overlap)
+            .output_bit_depth(self.parameters.output_bit_depth)
+            .jpeg_quality(self.parameters.jpeg_quality)
+            .max_in_flight(self.parameters.max_in_flight)
+            .timeout(self.parameters.timeout)
         )
     }
 
+    #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+    fn with_auto_noise(&self, realcugan: RealCugan) -> RealCugan {
+        match self.model.filter(|_| self.parameters.auto_noise) {
+            Some(model) => realcugan.auto_noise(Some(std::sync::Arc::new(AutoNoise::new(model, self.for_model(model))))),
+            None => realcugan,
+        }
+    }
+
+    #[cfg(not(any(feature = "models-nose", feature = "models-pro", feature = "models-se")))]
+    fn with_auto_noise(&self, realcugan: RealCugan) -> RealCugan {
+        realcugan
+    }
+
+    #[track_caller]
     pub fn unwrap(&self) -> RealCugan {
-        self.build().unwrap()
+        match self.build() {
+            Ok(realcugan) => realcugan,
+            Err(e) => panic!("failed to build RealCugan: {}", e),
+        }
+    }
+
+    #[track_caller]
+    pub fn expect(&self, msg: &str) -> RealCugan {
+        match self.build() {
+            Ok(realcugan) => realcugan,
+            Err(e) => panic!("{}: {}", msg, e),
+        }
     }
 
 }
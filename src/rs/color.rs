@@ -0,0 +1,361 @@
+use std::sync::OnceLock;
+
+use image::{ColorType, DynamicImage, Rgba32FImage, RgbaImage, RgbImage};
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn build_lut(transfer: fn(f32) -> f32) -> [u8; 256] {
+    let mut lut = [0; 256];
+    for (i, value) in lut.iter_mut().enumerate() {
+        *value = (transfer(i as f32 / 255.0) * 255.0).round().clamp(0.0, 255.0) as u8;
+    }
+    lut
+}
+
+fn map_bytes(bytes: &mut [u8], channels: usize, lut: &[u8; 256]) {
+    let color_channels = match channels {
+        2 | 4 => channels - 1,
+        _ => channels,
+    };
+    for pixel in bytes.chunks_exact_mut(channels) {
+        for value in &mut pixel[..color_channels] {
+            *value = lut[*value as usize];
+        }
+    }
+}
+
+fn image_bytes(image: &mut DynamicImage) -> Option<(&mut [u8], usize)> {
+    match image {
+        DynamicImage::ImageLuma8(buffer) => Some((buffer, 1)),
+        DynamicImage::ImageLumaA8(buffer) => Some((buffer, 2)),
+        DynamicImage::ImageRgb8(buffer) => Some((buffer, 3)),
+        DynamicImage::ImageRgba8(buffer) => Some((buffer, 4)),
+        _ => None,
+    }
+}
+
+fn map_color_channels(image: &mut DynamicImage, lut: &[u8; 256]) {
+    if let Some((bytes, channels)) = image_bytes(image) {
+        map_bytes(bytes, channels, lut);
+    }
+}
+
+fn srgb_lut() -> &'static [u8; 256] {
+    static LUT: OnceLock<[u8; 256]> = OnceLock::new();
+    LUT.get_or_init(|| build_lut(linear_to_srgb))
+}
+
+pub(crate) fn to_linear(image: &mut DynamicImage) {
+    static LUT: OnceLock<[u8; 256]> = OnceLock::new();
+    map_color_channels(image, LUT.get_or_init(|| build_lut(srgb_to_linear)));
+}
+
+pub(crate) fn to_srgb(image: &mut DynamicImage) {
+    map_color_channels(image, srgb_lut());
+}
+
+pub(crate) fn bytes_to_srgb(bytes: &mut [u8], channels: u8) {
+    map_bytes(bytes, usize::from(channels), srgb_lut());
+}
+
+pub(crate) fn to_color_type(image: DynamicImage, color: ColorType) -> DynamicImage {
+    if image.color() == color {
+        return image
+    }
+    match color {
+        ColorType::L8 => DynamicImage::from(image.into_luma8()),
+        ColorType::La8 => DynamicImage::from(image.into_luma_alpha8()),
+        ColorType::Rgb8 => DynamicImage::from(image.into_rgb8()),
+        ColorType::Rgba8 => DynamicImage::from(image.into_rgba8()),
+        ColorType::L16 => DynamicImage::from(image.to_luma16()),
+        ColorType::La16 => DynamicImage::from(image.to_luma_alpha16()),
+        ColorType::Rgb16 => DynamicImage::from(image.to_rgb16()),
+        ColorType::Rgba16 => DynamicImage::from(image.to_rgba16()),
+        ColorType::Rgb32F => DynamicImage::from(image.into_rgb32f()),
+        ColorType::Rgba32F => DynamicImage::from(image.into_rgba32f()),
+        _ => image,
+    }
+}
+
+// Keeps the channels and changes only the sample type. 8 bit samples are
+// widened by bit replication (x * 257), so 255 maps to 65535.
+pub(crate) fn to_bit_depth(image: DynamicImage, depth: u8) -> DynamicImage {
+    let channels = image.color().channel_count();
+    let color = match (depth, channels) {
+        (16, 1) => ColorType::L16,
+        (16, 2) => ColorType::La16,
+        (16, 4) => ColorType::Rgba16,
+        (16, _) => ColorType::Rgb16,
+        (_, 1) => ColorType::L8,
+        (_, 2) => ColorType::La8,
+        (_, 4) => ColorType::Rgba8,
+        _ => ColorType::Rgb8,
+    };
+    to_color_type(image, color)
+}
+
+// Only images with an alpha channel are changed, the rest pass through.
+fn map_alpha_pixels(bytes: &mut [u8], channels: usize, transfer: fn(u8, u8) -> u8) {
+    if channels != 2 && channels != 4 {
+        return
+    }
+    for pixel in bytes.chunks_exact_mut(channels) {
+        let (color, alpha) = pixel.split_at_mut(channels - 1);
+        for value in color {
+            *value = transfer(*value, alpha[0]);
+        }
+    }
+}
+
+fn premultiply_value(value: u8, alpha: u8) -> u8 {
+    ((u32::from(value) * u32::from(alpha) + 127) / 255) as u8
+}
+
+fn unpremultiply_value(value: u8, alpha: u8) -> u8 {
+    if alpha == 0 {
+        return 0
+    }
+    ((u32::from(value) * 255 + u32::from(alpha) / 2) / u32::from(alpha)).min(255) as u8
+}
+
+pub(crate) fn premultiply(image: &mut DynamicImage) {
+    if let Some((bytes, channels)) = image_bytes(image) {
+        map_alpha_pixels(bytes, channels, premultiply_value);
+    }
+}
+
+pub(crate) fn unpremultiply(image: &mut DynamicImage) {
+    if let Some((bytes, channels)) = image_bytes(image) {
+        map_alpha_pixels(bytes, channels, unpremultiply_value);
+    }
+}
+
+pub(crate) fn bytes_premultiply(bytes: &mut [u8], channels: u8) {
+    map_alpha_pixels(bytes, usize::from(channels), premultiply_value);
+}
+
+// HDR values are compressed with x / (1 + x) and sRGB encoded so they fit
+// in 8 bits, and expanded again after processing. Values above about 250
+// all end up in the top code.
+fn tone_map_value(value: f32) -> u8 {
+    let value = value.max(0.0);
+    (linear_to_srgb(value / (1.0 + value)) * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+fn inverse_tone_map_value(value: u8) -> f32 {
+    static LUT: OnceLock<[f32; 256]> = OnceLock::new();
+    LUT.get_or_init(|| {
+        let mut lut = [0.0; 256];
+        for (i, value) in lut.iter_mut().enumerate() {
+            let compressed = srgb_to_linear(i as f32 / 255.0).min(254.5 / 255.0);
+            *value = compressed / (1.0 - compressed);
+        }
+        lut
+    })[usize::from(value)]
+}
+
+pub(crate) fn tone_map(image: &Rgba32FImage) -> RgbaImage {
+    let bytes = image.as_raw()
+        .chunks_exact(4)
+        .flat_map(|pixel| [
+            tone_map_value(pixel[0]),
+            tone_map_value(pixel[1]),
+            tone_map_value(pixel[2]),
+            (pixel[3].clamp(0.0, 1.0) * 255.0).round() as u8,
+        ])
+        .collect();
+    RgbaImage::from_raw(image.width(), image.height(), bytes).unwrap_or_default()
+}
+
+pub(crate) fn inverse_tone_map(image: &RgbaImage) -> Rgba32FImage {
+    let values = image.as_raw()
+        .chunks_exact(4)
+        .flat_map(|pixel| [
+            inverse_tone_map_value(pixel[0]),
+            inverse_tone_map_value(pixel[1]),
+            inverse_tone_map_value(pixel[2]),
+            f32::from(pixel[3]) / 255.0,
+        ])
+        .collect();
+    Rgba32FImage::from_raw(image.width(), image.height(), values).unwrap_or_default()
+}
+
+// Limited range BT.601, as produced by most video decoders. The chroma planes
+// are half the luma size rounded up.
+pub(crate) fn yuv420_to_rgb(y: &[u8], u: &[u8], v: &[u8], width: u32, height: u32) -> RgbImage {
+    let chroma_width = width.div_ceil(2) as usize;
+    RgbImage::from_fn(width, height, |x, row| {
+        let luma = y[row as usize * width as usize + x as usize];
+        let chroma = (row / 2) as usize * chroma_width + (x / 2) as usize;
+        let luma = 1.164 * (f32::from(luma) - 16.0);
+        let cb = f32::from(u[chroma]) - 128.0;
+        let cr = f32::from(v[chroma]) - 128.0;
+        let channel = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+        image::Rgb([
+            channel(luma + 1.596 * cr),
+            channel(luma - 0.392 * cb - 0.813 * cr),
+            channel(luma + 2.017 * cb),
+        ])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn linear_lut() -> [u8; 256] {
+        build_lut(srgb_to_linear)
+    }
+
+    #[test]
+    fn lut_endpoints() {
+        let (linear, srgb) = (linear_lut(), srgb_lut());
+        assert_eq!((linear[0], linear[255]), (0, 255));
+        assert_eq!((srgb[0], srgb[255]), (0, 255));
+        assert!(linear.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(srgb.windows(2).all(|pair| pair[0] <= pair[1]));
+        // mid gray in sRGB is about a fifth of the light
+        assert_eq!(linear[128], 55);
+        assert_eq!(srgb[55], 128);
+    }
+
+    #[test]
+    fn lut_round_trip() {
+        let (linear, srgb) = (linear_lut(), srgb_lut());
+        for value in 0..=255u8 {
+            let back = linear[usize::from(srgb[usize::from(value)])];
+            assert!(back.abs_diff(value) <= 1, "{} came back as {}", value, back);
+        }
+        // the other way dark values share a code in linear light
+        for value in 0..=255u8 {
+            let back = srgb[usize::from(linear[usize::from(value)])];
+            assert!(back.abs_diff(value) <= 6, "{} came back as {}", value, back);
+        }
+        for step in 0..=100 {
+            let value = step as f32 / 100.0;
+            assert!((linear_to_srgb(srgb_to_linear(value)) - value).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn map_bytes_keeps_alpha() {
+        let mut bytes = [128, 128, 128, 128, 128, 128];
+        map_bytes(&mut bytes, 2, srgb_lut());
+        assert_eq!(bytes, [srgb_lut()[128], 128, srgb_lut()[128], 128, srgb_lut()[128], 128]);
+    }
+
+    #[test]
+    fn premultiply_values() {
+        assert_eq!(premultiply_value(255, 255), 255);
+        assert_eq!(premultiply_value(200, 0), 0);
+        assert_eq!(premultiply_value(255, 128), 128);
+        assert_eq!(premultiply_value(100, 51), 20);
+        assert_eq!(unpremultiply_value(0, 0), 0);
+        assert_eq!(unpremultiply_value(200, 0), 0);
+        assert_eq!(unpremultiply_value(128, 128), 255);
+        // values above alpha are not valid premultiplied input and clamp
+        assert_eq!(unpremultiply_value(200, 100), 255);
+        for alpha in 1..=255u8 {
+            for value in [0, 1, 64, 200, 255] {
+                let back = unpremultiply_value(premultiply_value(value, alpha), alpha);
+                // one code of the premultiplied value spans 255 / alpha codes
+                assert!(u32::from(back.abs_diff(value)) <= 255 / u32::from(alpha) / 2 + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn premultiply_bytes() {
+        let mut rgba = [200, 100, 50, 0, 255, 255, 255, 128];
+        bytes_premultiply(&mut rgba, 4);
+        assert_eq!(rgba, [0, 0, 0, 0, 128, 128, 128, 128]);
+        let mut rgb = [200, 100, 50];
+        bytes_premultiply(&mut rgb, 3);
+        assert_eq!(rgb, [200, 100, 50]);
+        let mut gray_alpha = [255, 51];
+        map_alpha_pixels(&mut gray_alpha, 2, premultiply_value);
+        assert_eq!(gray_alpha, [51, 51]);
+    }
+
+    #[test]
+    fn tone_map_values() {
+        assert_eq!(tone_map_value(0.0), 0);
+        assert_eq!(tone_map_value(-1.0), 0);
+        assert_eq!(tone_map_value(1.0e6), 255);
+        assert_eq!(inverse_tone_map_value(0), 0.0);
+        assert!(inverse_tone_map_value(255).is_finite());
+        let mut previous = 0;
+        for value in [0.01, 0.1, 0.5, 1.0, 4.0, 16.0, 64.0] {
+            let mapped = tone_map_value(value);
+            assert!(mapped > previous);
+            previous = mapped;
+            // the codes get coarse towards the top, where all large values end up
+            let back = inverse_tone_map_value(mapped);
+            assert!(value > 16.0 || (back - value).abs() / value < 0.1, "{} came back as {}", value, back);
+        }
+    }
+
+    #[test]
+    fn tone_map_image() {
+        let image = Rgba32FImage::from_raw(1, 1, vec![1.0, 0.0, 4.0, 2.0]).unwrap();
+        let mapped = tone_map(&image);
+        assert_eq!(mapped.as_raw(), &vec![tone_map_value(1.0), 0, tone_map_value(4.0), 255]);
+        let back = inverse_tone_map(&mapped);
+        assert_eq!(back.as_raw()[3], 1.0);
+    }
+
+    #[test]
+    fn bit_depth() {
+        let image = DynamicImage::from(RgbImage::from_raw(1, 1, vec![255, 128, 0]).unwrap());
+        let wide = to_bit_depth(image.clone(), 16);
+        assert_eq!(wide.color(), ColorType::Rgb16);
+        assert_eq!(wide.to_rgb16().as_raw(), &vec![65535, 128 * 257, 0]);
+        assert_eq!(to_bit_depth(wide, 8).color(), ColorType::Rgb8);
+        assert_eq!(to_bit_depth(image.clone(), 8).color(), ColorType::Rgb8);
+        let gray_alpha = DynamicImage::from(image.to_luma_alpha8());
+        assert_eq!(to_bit_depth(gray_alpha, 16).color(), ColorType::La16);
+    }
+
+    #[test]
+    fn yuv420_bt601() {
+        // limited range black, white and gray, then the BT.601 primaries
+        let cases = [
+            (16, 128, 128, [0, 0, 0]),
+            (235, 128, 128, [255, 255, 255]),
+            (126, 128, 128, [128, 128, 128]),
+            (81, 90, 240, [254, 0, 0]),
+            (145, 54, 34, [0, 255, 1]),
+            (41, 240, 110, [0, 0, 255]),
+        ];
+        for (y, u, v, rgb) in cases {
+            let image = yuv420_to_rgb(&[y], &[u], &[v], 1, 1);
+            assert_eq!(image.get_pixel(0, 0).0, rgb, "yuv {} {} {}", y, u, v);
+        }
+    }
+
+    #[test]
+    fn yuv420_chroma_subsampling() {
+        // three columns and rows share two chroma samples in each direction
+        let y = [235; 9];
+        let u = [128, 128, 128, 128];
+        let v = [128, 255, 128, 255];
+        let image = yuv420_to_rgb(&y, &u, &v, 3, 3);
+        assert_eq!(image.get_pixel(1, 1).0, [255, 255, 255]);
+        assert_eq!(image.get_pixel(2, 0).0, image.get_pixel(2, 2).0);
+        assert_ne!(image.get_pixel(2, 0).0, [255, 255, 255]);
+    }
+}
@@ -0,0 +1,82 @@
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ProcessErrorKind {
+    OutOfMemory,     // -100
+    InvalidInput,    // -1
+    DeviceLost,      // -200
+    InferenceFailed, // -300
+    Unknown,
+}
+
+impl ProcessErrorKind {
+    pub(crate) fn from_code(code: i32) -> Self {
+        match code {
+            -100 => ProcessErrorKind::OutOfMemory,
+            -1 => ProcessErrorKind::InvalidInput,
+            -200 => ProcessErrorKind::DeviceLost,
+            -300 => ProcessErrorKind::InferenceFailed,
+            _ => ProcessErrorKind::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RealCuganError {
+    InvalidScale(i32),
+    GpuNotFound { gpu: i32, count: i32 },
+    NoGpuAvailable,
+    FilePointer,
+    LoadModel(i32),
+    InvalidPointer,
+    Process { code: i32, kind: ProcessErrorKind },
+    InvalidDimensions(String),
+    InvalidChannels(u8),
+    InvalidBitDepth(u8),
+    InvalidJpegQuality(u8),
+    Io(String),
+    Image(String),
+    SelfTest(String),
+    InvalidModelBlob(String),
+    NoModelProvided,
+    ModelMismatch(String),
+    Timeout(Duration),
+    InvalidOption(String),
+    Cancelled,
+    AutoNoiseWithoutModel,
+    Unsupported(String),
+    InvalidParameter(String),
+}
+
+impl fmt::Display for RealCuganError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RealCuganError::InvalidScale(scale) => write!(f, "invalid scale value: {}. expected 2, 3, or 4", scale),
+            RealCuganError::GpuNotFound { gpu, count } => write!(f, "gpu {} not found. available gpus: {}", gpu, count),
+            RealCuganError::NoGpuAvailable => write!(f, "no vulkan capable gpu found. check that a vulkan driver is installed"),
+            RealCuganError::FilePointer => write!(f, "failed to create file pointers"),
+            RealCuganError::LoadModel(code) => write!(f, "failed to load model files. error code: {}", code),
+            RealCuganError::InvalidPointer => write!(f, "invalid pointer"),
+            RealCuganError::Process { code, kind } => write!(f, "failed to process image: {:?} (error code: {})", kind, code),
+            RealCuganError::InvalidDimensions(message) => write!(f, "invalid dimensions: {}", message),
+            RealCuganError::InvalidChannels(channels) => write!(f, "invalid number of channels: {}", channels),
+            RealCuganError::InvalidBitDepth(depth) => write!(f, "invalid output bit depth: {}. expected 8 or 16", depth),
+            RealCuganError::InvalidJpegQuality(quality) => write!(f, "invalid jpeg quality: {}. expected 1 to 100", quality),
+            RealCuganError::Io(message) => write!(f, "{}", message),
+            RealCuganError::Image(message) => write!(f, "{}", message),
+            RealCuganError::SelfTest(message) => write!(f, "self test failed: {}", message),
+            RealCuganError::InvalidModelBlob(message) => write!(f, "invalid model blob: {}", message),
+            RealCuganError::ModelMismatch(message) => write!(f, "param and bin files do not match: {}", message),
+            RealCuganError::Timeout(timeout) => write!(f, "processing did not finish within {:?}", timeout),
+            RealCuganError::InvalidOption(message) => write!(f, "invalid ncnn option: {}", message),
+            RealCuganError::Cancelled => write!(f, "processing was cancelled"),
+            RealCuganError::AutoNoiseWithoutModel => write!(f, "auto_noise needs a built-in model set with model()"),
+            RealCuganError::Unsupported(message) => write!(f, "{}", message),
+            RealCuganError::InvalidParameter(message) => write!(f, "invalid parameter: {}", message),
+            RealCuganError::NoModelProvided => write!(f, "no model provided. set one with .model(), .model_files(), .model_bytes() or .model_blob()"),
+        }
+    }
+}
+
+impl std::error::Error for RealCuganError {}
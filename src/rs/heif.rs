@@ -0,0 +1,48 @@
+use crate::error::RealCuganError;
+
+use image::{DynamicImage, RgbImage, RgbaImage};
+use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+const BRANDS: [&[u8; 4]; 8] = [b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx", b"mif1", b"msf1"];
+
+pub(crate) fn is_heif(bytes: &[u8]) -> bool {
+    bytes.len() >= 12 && &bytes[4..8] == b"ftyp" && BRANDS.iter().any(|brand| &bytes[8..12] == *brand)
+}
+
+pub(crate) fn is_heif_path<P: AsRef<std::path::Path>>(path: &P) -> bool {
+    path.as_ref()
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.eq_ignore_ascii_case("heic") || extension.eq_ignore_ascii_case("heif"))
+        .unwrap_or(false)
+}
+
+// libheif applies the rotation and mirroring stored in the file while
+// decoding, so the pixels come out in display orientation.
+pub(crate) fn decode(bytes: &[u8]) -> Result<DynamicImage, RealCuganError> {
+    let error = |e: libheif_rs::HeifError| RealCuganError::Image(format!("failed to decode heif image: {}", e));
+
+    let context = HeifContext::read_from_bytes(bytes).map_err(error)?;
+    let handle = context.primary_image_handle().map_err(error)?;
+    let alpha = handle.has_alpha_channel();
+    let chroma = if alpha { RgbChroma::Rgba } else { RgbChroma::Rgb };
+    let image = LibHeif::new().decode(&handle, ColorSpace::Rgb(chroma), None).map_err(error)?;
+
+    let planes = image.planes();
+    let plane = planes.interleaved
+        .ok_or(RealCuganError::Image("failed to decode heif image: missing interleaved plane".to_string()))?;
+    let channels = if alpha { 4 } else { 3 };
+    let row_length = plane.width as usize * channels;
+    let bytes = plane.data
+        .chunks(plane.stride)
+        .take(plane.height as usize)
+        .flat_map(|row| &row[..row_length])
+        .copied()
+        .collect();
+
+    if alpha {
+        RgbaImage::from_raw(plane.width, plane.height, bytes).map(DynamicImage::from)
+    } else {
+        RgbImage::from_raw(plane.width, plane.height, bytes).map(DynamicImage::from)
+    }.ok_or(RealCuganError::InvalidChannels(channels as u8))
+}
@@ -1,8 +1,26 @@
+mod bench;
 mod builder;
+mod color;
+mod error;
+#[cfg(feature = "ncnn-logs")]
+mod log;
+#[cfg(feature = "heif")]
+mod heif;
+mod metrics;
+mod noise;
+mod options;
+mod pool;
 mod realcugan;
+mod tile;
 
 #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
 pub use builder::Model;
-pub use builder::SyncGap;
-pub use realcugan::RealCugan;
+pub use bench::BenchResult;
+pub use builder::{Builder, Precision, SyncGap, TileStrategy};
+pub use error::{ProcessErrorKind, RealCuganError};
+pub use metrics::QualityMetrics;
+pub use options::ProcessOptions;
+pub use pool::RealCuganPool;
+pub use realcugan::{shutdown, Backend, GpuInfo, InputImage, OutputBuffer, OwnedImage, RealCugan};
+pub use tile::TileRect;
 pub use image;
\ No newline at end of file
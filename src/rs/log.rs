@@ -0,0 +1,39 @@
+use std::ffi::CStr;
+use std::sync::RwLock;
+
+use libc::c_char;
+
+type Callback = Box<dyn Fn(&str) + Send + Sync>;
+
+static CALLBACK: RwLock<Option<Callback>> = RwLock::new(None);
+
+extern "C" {
+    fn realcugan_set_log_callback(callback: Option<extern "C" fn(*const c_char)>);
+}
+
+// Called from native code, so a panic in the user callback must not unwind
+// out of it.
+extern "C" fn forward(message: *const c_char) {
+    if message.is_null() {
+        return
+    }
+    let _ = std::panic::catch_unwind(|| {
+        let message = unsafe { CStr::from_ptr(message) }.to_string_lossy();
+        if let Some(callback) = CALLBACK.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            callback(message.trim_end());
+        }
+    });
+}
+
+pub(crate) fn set_callback(callback: Option<Callback>) {
+    let enabled = callback.is_some();
+    *CALLBACK.write().unwrap_or_else(|e| e.into_inner()) = callback;
+    unsafe { realcugan_set_log_callback(if enabled { Some(forward) } else { None }) }
+}
+
+// Reports errors from the Rust side through the same callback as ncnn.
+pub(crate) fn emit(message: &str) {
+    if let Some(callback) = CALLBACK.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        callback(message);
+    }
+}
@@ -0,0 +1,102 @@
+use image::{DynamicImage, GrayImage, RgbImage};
+
+use crate::error::RealCuganError;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct QualityMetrics {
+    pub psnr: f64,
+    pub ssim: f64,
+}
+
+const BLOCK_SIZE: u32 = 8;
+
+// PSNR over the RGB channels, infinite for identical images.
+fn psnr(a: &DynamicImage, b: &DynamicImage) -> f64 {
+    let (a, b) = (a.to_rgb8(), b.to_rgb8());
+    let squared_error: f64 = a.as_raw()
+        .iter()
+        .zip(b.as_raw())
+        .map(|(&a, &b)| (f64::from(a) - f64::from(b)).powi(2))
+        .sum();
+    let mse = squared_error / a.as_raw().len() as f64;
+    if mse == 0.0 {
+        return f64::INFINITY
+    }
+    10.0 * (255.0 * 255.0 / mse).log10()
+}
+
+fn block_ssim(a: &GrayImage, b: &GrayImage, x: u32, y: u32, width: u32, height: u32) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    let pixels = || (y..y + height).flat_map(move |row| (x..x + width).map(move |column| (column, row)));
+    let count = f64::from(width * height);
+    let (mut mean_a, mut mean_b) = (0.0, 0.0);
+    for (column, row) in pixels() {
+        mean_a += f64::from(a.get_pixel(column, row)[0]);
+        mean_b += f64::from(b.get_pixel(column, row)[0]);
+    }
+    mean_a /= count;
+    mean_b /= count;
+
+    let (mut variance_a, mut variance_b, mut covariance) = (0.0, 0.0, 0.0);
+    for (column, row) in pixels() {
+        let da = f64::from(a.get_pixel(column, row)[0]) - mean_a;
+        let db = f64::from(b.get_pixel(column, row)[0]) - mean_b;
+        variance_a += da * da;
+        variance_b += db * db;
+        covariance += da * db;
+    }
+    variance_a /= count;
+    variance_b /= count;
+    covariance /= count;
+
+    ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+        / ((mean_a * mean_a + mean_b * mean_b + C1) * (variance_a + variance_b + C2))
+}
+
+// SSIM of the luma, averaged over non-overlapping 8x8 blocks. Blocks on the
+// right and bottom edges are cut to the image size.
+fn ssim(a: &DynamicImage, b: &DynamicImage) -> f64 {
+    let (a, b) = (a.to_luma8(), b.to_luma8());
+    let (width, height) = a.dimensions();
+    let mut sum = 0.0;
+    let mut blocks = 0;
+    for y in (0..height).step_by(BLOCK_SIZE as usize) {
+        for x in (0..width).step_by(BLOCK_SIZE as usize) {
+            let block_width = BLOCK_SIZE.min(width - x);
+            let block_height = BLOCK_SIZE.min(height - y);
+            sum += block_ssim(&a, &b, x, y, block_width, block_height);
+            blocks += 1;
+        }
+    }
+    sum / f64::from(blocks)
+}
+
+pub(crate) fn compare(a: &DynamicImage, b: &DynamicImage) -> Result<QualityMetrics, RealCuganError> {
+    if (a.width(), a.height()) != (b.width(), b.height()) {
+        return Err(RealCuganError::InvalidDimensions(format!(
+            "cannot compare {}x{} with {}x{}",
+            a.width(), a.height(), b.width(), b.height()
+        )))
+    }
+    if a.width() == 0 || a.height() == 0 {
+        return Err(RealCuganError::InvalidDimensions(format!("empty image: {}x{}", a.width(), a.height())))
+    }
+    Ok(QualityMetrics {
+        psnr: psnr(a, b),
+        ssim: ssim(a, b),
+    })
+}
+
+// Signed per-channel difference of the RGB channels, offset so that no
+// difference is mid gray (128) and clamped to the 8 bit range.
+pub(crate) fn residual(output: &DynamicImage, baseline: &DynamicImage) -> RgbImage {
+    let (output, baseline) = (output.to_rgb8(), baseline.to_rgb8());
+    let bytes = output.as_raw()
+        .iter()
+        .zip(baseline.as_raw())
+        .map(|(&a, &b)| (i16::from(a) - i16::from(b) + 128).clamp(0, 255) as u8)
+        .collect();
+    RgbImage::from_raw(output.width(), output.height(), bytes).unwrap_or_default()
+}
@@ -0,0 +1,37 @@
+use image::DynamicImage;
+
+// Immerkær's fast noise variance estimate: the luma is convolved with a
+// kernel that cancels image structure up to second order, and the mean
+// absolute response is proportional to the standard deviation of the noise.
+pub(crate) fn estimate_sigma(image: &DynamicImage) -> f64 {
+    let luma = image.to_luma8();
+    let (width, height) = (luma.width() as usize, luma.height() as usize);
+    if width < 3 || height < 3 {
+        return 0.0
+    }
+    let pixels = luma.as_raw();
+    let at = |x: usize, y: usize| f64::from(pixels[y * width + x]);
+
+    let mut sum = 0.0;
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let response = at(x - 1, y - 1) - 2.0 * at(x, y - 1) + at(x + 1, y - 1)
+                - 2.0 * at(x - 1, y) + 4.0 * at(x, y) - 2.0 * at(x + 1, y)
+                + at(x - 1, y + 1) - 2.0 * at(x, y + 1) + at(x + 1, y + 1);
+            sum += response.abs();
+        }
+    }
+    sum * (std::f64::consts::PI / 2.0).sqrt() / (6.0 * (width - 2) as f64 * (height - 2) as f64)
+}
+
+// Noise levels in the order the models denoise: 0 none, -1 conservative,
+// then 1 to 3.
+pub(crate) fn estimate_level(image: &DynamicImage) -> i32 {
+    match estimate_sigma(image) {
+        sigma if sigma < 1.5 => 0,
+        sigma if sigma < 3.0 => -1,
+        sigma if sigma < 6.0 => 1,
+        sigma if sigma < 10.0 => 2,
+        _ => 3,
+    }
+}
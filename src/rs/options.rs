@@ -0,0 +1,74 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::error::RealCuganError;
+
+type ProgressFn = dyn Fn(usize, usize) + Send + Sync;
+
+// Per call settings, so the builder only holds what belongs to the instance.
+#[derive(Clone, Default)]
+pub struct ProcessOptions {
+    pub(crate) timeout: Option<Duration>,
+    cancel: Option<Arc<AtomicBool>>,
+    progress: Option<Arc<ProgressFn>>,
+}
+
+impl ProcessOptions {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn cancel(mut self, token: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    pub fn progress(mut self, progress: impl Fn(usize, usize) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    pub(crate) fn without_timeout(&self) -> Self {
+        Self {
+            timeout: None,
+            ..self.clone()
+        }
+    }
+
+    // Progress and cancellation are checked between tiles, so they need the
+    // tiles to be cut on the Rust side.
+    pub(crate) fn needs_tiles(&self) -> bool {
+        self.cancel.is_some() || self.progress.is_some()
+    }
+
+    pub(crate) fn check_cancelled(&self) -> Result<(), RealCuganError> {
+        match &self.cancel {
+            Some(token) if token.load(Ordering::Acquire) => Err(RealCuganError::Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn report(&self, done: usize, total: usize) {
+        if let Some(progress) = &self.progress {
+            progress(done, total);
+        }
+    }
+
+}
+
+impl std::fmt::Debug for ProcessOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProcessOptions")
+            .field("timeout", &self.timeout)
+            .field("cancel", &self.cancel)
+            .field("progress", &self.progress.is_some())
+            .finish()
+    }
+}
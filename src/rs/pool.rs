@@ -0,0 +1,150 @@
+use crate::builder::Builder;
+use crate::color;
+use crate::error::RealCuganError;
+use crate::realcugan::{Backend, RealCugan};
+use crate::tile::{Blender, TileRect};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use image::DynamicImage;
+
+#[derive(Debug)]
+pub struct RealCuganPool {
+    instances: Vec<RealCugan>,
+    next: AtomicUsize,
+}
+
+impl RealCuganPool {
+
+    pub fn new(gpus: &[u32], builder: &Builder) -> Result<Self, RealCuganError> {
+        if gpus.is_empty() {
+            return Err(RealCuganError::InvalidParameter("no gpus given".to_string()))
+        }
+        let instances = gpus
+            .iter()
+            .map(|gpu| builder.clone().gpu(*gpu).build())
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            instances,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    pub fn instances(&self) -> &[RealCugan] {
+        &self.instances
+    }
+
+    fn next_instance(&self) -> &RealCugan {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.instances.len();
+        &self.instances[index]
+    }
+
+    pub fn process_image(&self, image: DynamicImage) -> Result<DynamicImage, RealCuganError> {
+        self.next_instance().process_image(image)
+    }
+
+    pub fn process_images(&self, images: Vec<DynamicImage>) -> Vec<Result<DynamicImage, RealCuganError>> {
+        let count = self.instances.len();
+        let mut queues: Vec<Vec<(usize, DynamicImage)>> = (0..count).map(|_| Vec::new()).collect();
+        for (index, image) in images.into_iter().enumerate() {
+            queues[index % count].push((index, image));
+        }
+
+        let mut results: Vec<(usize, Result<DynamicImage, RealCuganError>)> = std::thread::scope(|scope| {
+            let handles: Vec<_> = self.instances
+                .iter()
+                .zip(queues)
+                .map(|(realcugan, queue)| scope.spawn(move || {
+                    queue
+                        .into_iter()
+                        .map(|(index, image)| (index, realcugan.process_image(image)))
+                        .collect::<Vec<_>>()
+                }))
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+                .collect()
+        });
+        results.sort_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+
+    // An empty list selects every instance.
+    fn instances_on(&self, gpus: &[u32]) -> Result<Vec<&RealCugan>, RealCuganError> {
+        if gpus.is_empty() {
+            return Ok(self.instances.iter().collect())
+        }
+        gpus.iter()
+            .map(|gpu| self.instances
+                .iter()
+                .find(|realcugan| realcugan.backend() == Backend::Gpu(*gpu))
+                .ok_or(RealCuganError::GpuNotFound { gpu: *gpu as i32, count: self.instances.len() as i32 }))
+            .collect()
+    }
+
+    // The image is cut into one horizontal band per gpu. Every band is
+    // extended by the model padding into its neighbours, so each side of a
+    // seam is processed with real context, and the overlap is blended.
+    pub fn process_large(&self, image: &DynamicImage, gpus: &[u32]) -> Result<DynamicImage, RealCuganError> {
+        let instances = self.instances_on(gpus)?;
+        let first = instances[0];
+        let scale = first.native_scale() as u32;
+        let padding = first.prepadding();
+        let (width, height) = (image.width(), image.height());
+        let rows = height.div_ceil(instances.len() as u32).max(1);
+        let bands = height.div_ceil(rows);
+        if bands <= 1 {
+            return first.process_image_ref(image)
+        }
+
+        let regions: Vec<TileRect> = (0..bands)
+            .map(|band| {
+                let top = (band * rows).saturating_sub(padding);
+                let bottom = ((band + 1) * rows + padding).min(height);
+                TileRect { x: 0, y: top, width, height: bottom - top }
+            })
+            .collect();
+        let outputs: Vec<Result<DynamicImage, RealCuganError>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = instances
+                .iter()
+                .zip(&regions)
+                .map(|(realcugan, region)| scope.spawn(move || {
+                    realcugan.process_image(image.crop_imm(region.x, region.y, region.width, region.height))
+                }))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+                .collect()
+        });
+
+        // blending works on 8 bit samples, the output type is restored after.
+        // The bands come out of the network at 8 bits, so converting them
+        // back only drops the samples that were widened from 8 bits.
+        let mut blender: Option<(Blender, u8, image::ColorType)> = None;
+        for (region, output) in regions.iter().zip(outputs) {
+            let output = output?;
+            let color = output.color();
+            let output = color::to_bit_depth(output, 8);
+            let channels = output.color().channel_count();
+            let (blender, _, _) = blender.get_or_insert_with(|| {
+                (Blender::new(width * scale, height * scale, channels, padding * 2 * scale), channels, color)
+            });
+            blender.add(region.scaled(scale), output.as_bytes());
+        }
+        let (blender, channels, color) = blender
+            .ok_or(RealCuganError::InvalidDimensions(format!("empty image: {}x{}", width, height)))?;
+        let output = RealCugan::convert_image(width * scale, height * scale, channels, blender.finish())?;
+        Ok(color::to_color_type(output, color))
+    }
+
+}
@@ -1,24 +1,144 @@
-use crate::builder::Builder;
+use crate::bench::BenchResult;
+use crate::builder::{Builder, Precision, SyncGap, TileStrategy};
+use crate::color;
+use crate::tile::{self, Blender, TileHook, TileRect};
+use crate::error::{ProcessErrorKind, RealCuganError};
+use crate::metrics::{self, QualityMetrics};
+use crate::options::ProcessOptions;
+#[cfg(feature = "heif")]
+use crate::heif;
 #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
 use crate::builder::Model;
 
+use std::borrow::Cow;
+use std::ffi::CString;
 use std::io::Cursor;
+use std::marker::PhantomData;
+#[cfg(feature = "tiff")]
+use std::io::{Seek, Write};
 use std::path::Path;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicPtr, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicPtr, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
-use image::{DynamicImage, GrayAlphaImage, GrayImage, RgbImage, RgbaImage};
+use image::{DynamicImage, GrayAlphaImage, GrayImage, ImageDecoder, ImageEncoder, ImageFormat, RgbImage, RgbaImage};
+use image::imageops::FilterType;
 use libc::{c_char, c_int, c_uchar, c_uint, c_void, FILE};
 
-static INSTANCES: AtomicU8 = AtomicU8::new(0);
+// ncnn keeps a single Vulkan instance for all devices and
+// realcugan_destroy_gpu_instance tears it down for every gpu at once, so
+// live instances are counted across all gpus rather than per device.
+static INSTANCES: AtomicUsize = AtomicUsize::new(0);
+
+// Held from validating a gpu until the new instance is counted, and while
+// the last instance tears the Vulkan instance down, so a failed build or a
+// drop in one thread cannot destroy it under an instance being created in
+// another.
+static LIFECYCLE: Mutex<()> = Mutex::new(());
+
+fn lifecycle() -> MutexGuard<'static, ()> {
+    LIFECYCLE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// Queries like list_gpus() create the Vulkan instance without a RealCugan
+// to tear it down again, so leak checkers need a way to do it explicitly.
+// Returns false and leaves it alone while instances are still alive.
+pub fn shutdown() -> bool {
+    let _lifecycle = lifecycle();
+    if INSTANCES.load(Ordering::Acquire) != 0 {
+        return false
+    }
+    unsafe { realcugan_destroy_gpu_instance() }
+    true
+}
+
+const MIN_TILE_SIZE: i32 = 32;
 
 #[repr(C)]
 #[derive(Debug)]
-pub struct Image {
-    pub data: *const c_uchar,
-    pub w: c_int,
-    pub h: c_int,
-    pub c: c_int,
+pub(crate) struct Image {
+    data: *const c_uchar,
+    w: c_int,
+    h: c_int,
+    c: c_int,
+}
+
+// Interleaved 8 bit pixels in the layout the native code expects. The view
+// from as_ffi() borrows the owned buffer, so it cannot outlive it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OwnedImage {
+    data: Vec<u8>,
+    width: u32,
+    height: u32,
+    channels: u8,
+}
+
+impl OwnedImage {
+    pub fn new(data: Vec<u8>, width: u32, height: u32, channels: u8) -> Result<Self, RealCuganError> {
+        if !(1..=4).contains(&channels) {
+            return Err(RealCuganError::InvalidChannels(channels))
+        }
+        let length = u64::from(width) * u64::from(height) * u64::from(channels);
+        if width == 0 || height == 0 || c_int::try_from(width).is_err() || c_int::try_from(height).is_err() || data.len() as u64 != length {
+            return Err(RealCuganError::InvalidDimensions(format!("{} bytes for a {}x{} image with {} channels", data.len(), width, height, channels)))
+        }
+        Ok(Self { data, width, height, channels })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn as_ffi(&self) -> InputImage<'_> {
+        InputImage::new(&self.data, self.width as c_int, self.height as c_int, c_int::from(self.channels))
+    }
+}
+
+impl TryFrom<OwnedImage> for DynamicImage {
+    type Error = RealCuganError;
+
+    fn try_from(image: OwnedImage) -> Result<Self, Self::Error> {
+        RealCugan::convert_image(image.width, image.height, image.channels, image.data)
+    }
+}
+
+// The only way to get an input Image, so its data pointer cannot outlive
+// the pixels it was made from. Transparent over the #[repr(C)] layout, so
+// FFI callers can pass a pointer to it as is.
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct InputImage<'a> {
+    image: Image,
+    pixels: PhantomData<&'a [u8]>,
+}
+
+impl<'a> InputImage<'a> {
+    fn new(pixels: &'a [u8], w: c_int, h: c_int, c: c_int) -> Self {
+        InputImage {
+            image: Image { data: pixels.as_ptr(), w, h, c },
+            pixels: PhantomData,
+        }
+    }
+
+    fn raw(&self) -> &Image {
+        &self.image
+    }
 }
 
 extern "C" {
@@ -26,8 +146,11 @@ extern "C" {
         gpuid: c_int,
         tta_mode: bool,
         num_threads: c_int,
+        precision: c_int,
     ) -> *mut c_void;
 
+    fn realcugan_set_option(realcugan: *mut c_void, key: *const c_char, value: bool) -> c_int;
+
     fn realcugan_set_parameters(
         realcugan: *mut c_void,
         scale: c_int,
@@ -43,6 +166,8 @@ extern "C" {
 
     fn realcugan_get_heap_budget(gpuid: c_int) -> c_uint;
 
+    fn realcugan_get_gpu_info(gpuid: c_int, info: *mut RawGpuInfo) -> c_int;
+
     fn realcugan_free_image(mat_ptr: *mut c_void);
 
     fn realcugan_free(realcugan: *mut c_void);
@@ -56,80 +181,331 @@ extern "C" {
     fn realcugan_process(
         realcugan: *mut c_void,
         in_image: *const Image,
-        out_image: *const Image,
+        out_image: *mut Image,
         mat_ptr: *mut *mut c_void,
     ) -> c_int;
 
     fn realcugan_process_cpu(
         realcugan: *mut c_void,
-        in_image: &Image,
-        out_image: &Image,
+        in_image: *const Image,
+        out_image: *mut Image,
         mat_ptr: *mut *mut c_void,
     ) -> c_int;
 }
 
+#[repr(C)]
+struct RawGpuInfo {
+    name: [u8; 256],
+    vendor_id: c_uint,
+    device_id: c_uint,
+    vram_mb: c_uint,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpuInfo {
+    pub name: String,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub vram_mb: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Backend {
+    Gpu(u32),
+    Cpu,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct NativeOptions {
+    pub(crate) precision: Option<Precision>,
+    pub(crate) ncnn: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Parameters {
+    scale: i32,
+    noise: i32,
+    prepadding: i32,
+    sync_gap: i32,
+    tile_size: i32,
+}
+
+// What a native instance was created from, kept so it can be created again
+// on another device.
+#[derive(Debug)]
+struct Setup {
+    threads: i32,
+    tta: bool,
+    options: NativeOptions,
+    param: Vec<u8>,
+    bin: Vec<u8>,
+}
+
+// Shared by every clone. Calls into the native instance hold the lock shared
+// and rebind_gpu and load_model hold it exclusively while they change it.
+#[derive(Debug)]
+struct Device {
+    gpu: AtomicI32,
+    allow_sync_gap: AtomicBool,
+    lock: RwLock<()>,
+    setup: Option<Mutex<Setup>>,
+}
+
+// State behind Builder::auto_noise(), shared by every clone. The other
+// models of the family are built on first use with the builder settings of
+// the instance and kept for later calls.
+#[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+#[derive(Debug)]
+pub(crate) struct AutoNoise {
+    model: Model,
+    builder: Builder<'static>,
+    instances: Mutex<std::collections::HashMap<Model, RealCugan>>,
+}
+
+#[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+impl AutoNoise {
+    pub(crate) fn new(model: Model, builder: Builder<'static>) -> Self {
+        Self { model, builder, instances: Mutex::new(std::collections::HashMap::new()) }
+    }
+
+    // Levels in order of denoise strength, so a level the family has no
+    // model for falls back to the closest one it has.
+    fn select(&self, image: &DynamicImage) -> Model {
+        const LEVELS: [i32; 5] = [0, -1, 1, 2, 3];
+        let rank = |noise: i32| LEVELS.iter().position(|level| *level == noise).unwrap_or(0);
+        let wanted = rank(crate::noise::estimate_level(image));
+        LEVELS.iter()
+            .filter_map(|level| self.model.with_noise(*level))
+            .min_by_key(|model| rank(model.noise()).abs_diff(wanted))
+            .unwrap_or(self.model)
+    }
+}
+
+// Owns the native instance and is shared by every clone, so the instance is
+// freed exactly once, when the Arc drops its last reference. Checking the
+// strong count in RealCugan's own Drop would race when two clones are
+// dropped at the same time.
+#[derive(Debug)]
+struct NativeHandle(AtomicPtr<c_void>);
+
+impl std::ops::Deref for NativeHandle {
+    type Target = AtomicPtr<c_void>;
+
+    fn deref(&self) -> &AtomicPtr<c_void> {
+        &self.0
+    }
+}
+
+#[derive(Debug)]
+pub struct OutputBuffer {
+    mat: *mut c_void,
+    data: *mut u8,
+    length: usize,
+    width: u32,
+    height: u32,
+    channels: u8,
+}
+
+unsafe impl Send for OutputBuffer {}
+
+impl OutputBuffer {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn channels(&self) -> u8 {
+        self.channels
+    }
+}
+
+impl std::ops::Deref for OutputBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.data, self.length) }
+    }
+}
+
+impl std::ops::DerefMut for OutputBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.data, self.length) }
+    }
+}
+
+impl Drop for OutputBuffer {
+    fn drop(&mut self) {
+        unsafe { realcugan_free_image(self.mat) }
+    }
+}
+
 #[derive(Debug)]
 pub struct RealCugan {
-    pointer: Arc<AtomicPtr<c_void>>,
-    scale_factor: i32,
-    use_cpu: bool
+    pointer: Arc<NativeHandle>,
+    parameters: Arc<Mutex<Parameters>>,
+    device: Arc<Device>,
+    keep_input_channels: bool,
+    match_input_type: bool,
+    auto_retry_oom: bool,
+    passthrough: Option<FilterType>,
+    linear_light: bool,
+    premultiplied_alpha: bool,
+    tile_strategy: TileStrategy,
+    tile_lock: Option<Arc<Mutex<()>>>,
+    tile_hook: Option<TileHook>,
+    timeout: Option<Duration>,
+    tile_overlap: u32,
+    output_bit_depth: Option<u8>,
+    jpeg_quality: Option<u8>,
+    max_in_flight: Option<usize>,
+    #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+    auto_noise: Option<Arc<AutoNoise>>,
 }
 
 unsafe impl Send for RealCugan {}
 
 impl RealCugan {
 
-    fn calculate_prepadding(scale: i32) -> Result<i32, String> {
+    fn calculate_prepadding(scale: i32) -> Result<i32, RealCuganError> {
         match scale {
             2 => Ok(18),
             3 => Ok(14),
             4 => Ok(19),
-            _ => Err(format!("invalid scale value: {}. expected 2, 3, or 4", scale))
+            _ => Err(RealCuganError::InvalidScale(scale))
+        }
+    }
+
+    // (heap budget in MB, tile size) pairs, largest tile first
+    fn tile_size_thresholds(scale: i32) -> &'static [(i32, i32)] {
+        match scale {
+            2 => &[(1300, 400), (800, 300), (200, 100)],
+            3 => &[(3300, 400), (1900, 300), (950, 200), (320, 100)],
+            4 => &[(1690, 400), (980, 300), (530, 200), (240, 100)],
+            _ => &[],
         }
     }
 
     fn calculate_tile_size(tile_size: i32, scale: i32, gpu: i32) -> i32 {
-        const DEFAULT_CPU_TILE_SIZE: i32 = 400;
-        const MIN_TILE_SIZE: i32 = 32;
-        
+        const DEFAULT_TILE_SIZE: i32 = 400;
+
         if tile_size != 0 {
             return tile_size;
         }
     
         if gpu == -1 {
-            return DEFAULT_CPU_TILE_SIZE;
+            return DEFAULT_TILE_SIZE;
         }
     
         let heap_budget = unsafe { realcugan_get_heap_budget(gpu) } as i32;
-        
-        let thresholds: Vec<(i32, i32)> = match scale {
-            2 => vec![(1300, 400), (800, 300), (200, 100)],
-            3 => vec![(3300, 400), (1900, 300), (950, 200), (320, 100)],
-            4 => vec![(1690, 400), (980, 300), (530, 200), (240, 100)],
-            _ => return MIN_TILE_SIZE,
-        };
-    
-        thresholds
+        // some drivers and virtual gpus report no budget at all, which would
+        // otherwise pick the smallest tile
+        if heap_budget <= 0 {
+            return DEFAULT_TILE_SIZE;
+        }
+
+        Self::budget_tile_size(heap_budget, scale)
+    }
+
+    fn budget_tile_size(heap_budget: i32, scale: i32) -> i32 {
+        Self::tile_size_thresholds(scale)
             .iter()
             .find(|(threshold, _)| heap_budget > *threshold)
             .map(|&(_, size)| size)
             .unwrap_or(MIN_TILE_SIZE)
     }
 
-    fn validate_gpu(gpu: i32) -> Result<(), String> {
+    // Reverses the thresholds above: just over the budget listed for the
+    // nearest tile size, since a size is only picked above its threshold,
+    // scaled with the tile area.
+    pub(crate) fn estimate_vram(tile_size: i32, scale: i32, width: u32, height: u32) -> u64 {
+        const MAX_AUTO_TILE_SIZE: i32 = 400;
+
+        let thresholds = Self::tile_size_thresholds(scale);
+        let Some(&largest) = thresholds.first() else {
+            return 0
+        };
+        let tile_size = if tile_size == 0 { MAX_AUTO_TILE_SIZE } else { tile_size };
+        let longest = i32::try_from(width.max(height)).unwrap_or(i32::MAX);
+        let tile_size = tile_size.min(longest).max(1);
+        let (budget, size) = thresholds
+            .iter()
+            .rev()
+            .find(|(_, size)| *size >= tile_size)
+            .copied()
+            .unwrap_or(largest);
+        let megabytes = f64::from(budget + 1) * (f64::from(tile_size) / f64::from(size)).powi(2);
+        (megabytes * 1024.0 * 1024.0) as u64
+    }
+
+    fn validate_gpu(gpu: i32) -> Result<(), RealCuganError> {
         if gpu == -1 {
             return Ok(())
         }
         let count = unsafe { realcugan_get_gpu_count() };
-        if gpu >= count {
-            if INSTANCES.load(Ordering::Relaxed) == 0 {
+        if gpu < 0 || gpu >= count {
+            if INSTANCES.load(Ordering::Acquire) == 0 {
                 unsafe { realcugan_destroy_gpu_instance() }
             }
-            return Err(format!("gpu {} not found. available gpus: {}", gpu, count))
+            if count == 0 {
+                return Err(RealCuganError::NoGpuAvailable)
+            }
+            return Err(RealCuganError::GpuNotFound { gpu, count })
         }
         Ok(())
     }
 
+    pub fn ncnn_version() -> &'static str {
+        env!("REALCUGAN_NCNN_VERSION")
+    }
+
+    pub fn crate_version() -> &'static str {
+        env!("CARGO_PKG_VERSION")
+    }
+
+    #[cfg(feature = "ncnn-logs")]
+    pub fn set_log_callback<F: Fn(&str) + Send + Sync + 'static>(callback: F) {
+        crate::log::set_callback(Some(Box::new(callback)))
+    }
+
+    #[cfg(feature = "ncnn-logs")]
+    pub fn clear_log_callback() {
+        crate::log::set_callback(None)
+    }
+
+    pub fn list_gpus() -> Vec<GpuInfo> {
+        let count = {
+            let _lifecycle = lifecycle();
+            unsafe { realcugan_get_gpu_count() }
+        };
+        (0..count).filter_map(|gpu| Self::gpu_info(gpu).ok()).collect()
+    }
+
+    pub fn gpu_info(gpu: i32) -> Result<GpuInfo, RealCuganError> {
+        if gpu == -1 {
+            return Err(RealCuganError::GpuNotFound { gpu, count: unsafe { realcugan_get_gpu_count() } })
+        }
+        let _lifecycle = lifecycle();
+        Self::validate_gpu(gpu)?;
+
+        let mut info = RawGpuInfo { name: [0; 256], vendor_id: 0, device_id: 0, vram_mb: 0 };
+        let result = unsafe { realcugan_get_gpu_info(gpu, &mut info) };
+        if result != 0 {
+            return Err(RealCuganError::GpuNotFound { gpu, count: unsafe { realcugan_get_gpu_count() } })
+        }
+        let name = std::ffi::CStr::from_bytes_until_nul(&info.name)
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(GpuInfo {
+            name,
+            vendor_id: info.vendor_id,
+            device_id: info.device_id,
+            vram_mb: info.vram_mb,
+        })
+    }
+
     fn create_file_pointer(contents: &[u8]) -> *mut FILE {
         let buffer = contents.as_ptr() as *mut c_void;
         let size = contents.len();
@@ -137,18 +513,107 @@ impl RealCugan {
         unsafe { libc::fmemopen(buffer, size, "rb\0".as_ptr() as *const c_char) }
     }
 
-    fn load_model(realcugan: *mut c_void, param: &[u8], bin: &[u8]) -> Result<(), String> {
+    pub fn pack_model(param: &[u8], bin: &[u8]) -> Vec<u8> {
+        let mut blob = Vec::with_capacity(8 + param.len() + bin.len());
+        blob.extend_from_slice(&(param.len() as u64).to_le_bytes());
+        blob.extend_from_slice(param);
+        blob.extend_from_slice(bin);
+        blob
+    }
+
+    pub(crate) fn unpack_model(blob: &[u8]) -> Result<(&[u8], &[u8]), RealCuganError> {
+        let (header, contents) = blob
+            .split_first_chunk::<8>()
+            .ok_or(RealCuganError::InvalidModelBlob("missing param length header".to_string()))?;
+        let param_len = usize::try_from(u64::from_le_bytes(*header))
+            .ok()
+            .filter(|len| *len <= contents.len())
+            .ok_or(RealCuganError::InvalidModelBlob("param length exceeds blob size".to_string()))?;
+        Ok(contents.split_at(param_len))
+    }
+
+    fn check_param(param: &[u8]) -> Result<(), RealCuganError> {
+        const MAGIC: &str = "7767517";
+
+        let mut lines = param.split(|byte| *byte == b'\n').map(|line| String::from_utf8_lossy(line));
+        if lines.next().map(|line| line.trim() == MAGIC) != Some(true) {
+            return Err(RealCuganError::ModelMismatch(format!("param does not start with the ncnn magic number {}", MAGIC)))
+        }
+        let counts = lines.next().unwrap_or_default();
+        let counts: Vec<Option<usize>> = counts.split_whitespace().map(|count| count.parse().ok()).collect();
+        let layers = match counts.as_slice() {
+            [Some(layers), Some(blobs)] if *layers > 0 && *blobs > 0 => *layers,
+            _ => return Err(RealCuganError::ModelMismatch("param has an invalid layer and blob count header".to_string()))
+        };
+
+        // every layer line is "type name bottom_count top_count bottoms... tops... params..."
+        let mut found = 0;
+        for line in lines.filter(|line| !line.trim().is_empty()) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let blobs = match fields.as_slice() {
+                [_, _, bottoms, tops, ..] => bottoms.parse::<usize>().ok()
+                    .zip(tops.parse::<usize>().ok())
+                    .map(|(bottoms, tops)| bottoms.saturating_add(tops)),
+                _ => None,
+            };
+            if blobs.map(|blobs| fields.len() - 4 < blobs) != Some(false) {
+                return Err(RealCuganError::ModelMismatch(format!("param layer {} is malformed", found)))
+            }
+            found += 1;
+        }
+        if found < layers {
+            return Err(RealCuganError::ModelMismatch(format!("param declares {} layers but contains {}", layers, found)))
+        }
+        Ok(())
+    }
+
+    // The Input layer carries no shape, so the channel count comes from the
+    // weights of the first convolution: out * in * kernel_w * kernel_h.
+    fn input_channels(param: &[u8]) -> Option<u8> {
+        let param = String::from_utf8_lossy(param);
+        let fields: Vec<&str> = param
+            .lines()
+            .find(|line| line.split_whitespace().next() == Some("Convolution"))?
+            .split_whitespace()
+            .collect();
+        let value = |id: &str| fields.iter()
+            .filter_map(|field| field.split_once('='))
+            .find(|(key, _)| *key == id)
+            .and_then(|(_, value)| value.parse::<u64>().ok());
+        let outputs = value("0")?;
+        let kernel_width = value("1")?;
+        let kernel_height = value("11").unwrap_or(kernel_width);
+        let weights = value("6")?;
+        let per_channel = outputs.checked_mul(kernel_width)?.checked_mul(kernel_height)?;
+        if per_channel == 0 || !weights.is_multiple_of(per_channel) {
+            return None
+        }
+        u8::try_from(weights / per_channel).ok()
+    }
+
+    fn check_bin(bin: &[u8]) -> Result<(), RealCuganError> {
+        // ncnn stores every weight blob padded to 4 bytes
+        if !bin.len().is_multiple_of(4) {
+            return Err(RealCuganError::ModelMismatch(format!("bin size {} is not a multiple of 4", bin.len())))
+        }
+        Ok(())
+    }
+
+    fn load_weights(realcugan: *mut c_void, param: &[u8], bin: &[u8]) -> Result<(), RealCuganError> {
+        Self::check_param(param)?;
+        Self::check_bin(bin)?;
         let file_bin_pointer = Self::create_file_pointer(bin);
         let file_param_pointer = Self::create_file_pointer(param);
         if file_bin_pointer.is_null() || file_param_pointer.is_null() {
-            return Err(format!("failed to create file pointers"));
+            return Err(RealCuganError::FilePointer);
         }
         let result = unsafe { realcugan_load_files(realcugan, file_param_pointer, file_bin_pointer) };
 
-        if result != 0 {
-            Err(format!("failed to load model files. error code: {}", result))
-        } else {
-            Ok(())
+        match result {
+            0 => Ok(()),
+            -3 => Err(RealCuganError::ModelMismatch("bin is smaller than the layers in param require".to_string())),
+            -4 => Err(RealCuganError::ModelMismatch("bin is larger than the layers in param require".to_string())),
+            _ => Err(RealCuganError::LoadModel(result)),
         }
     }
 
@@ -162,176 +627,1593 @@ impl RealCugan {
         noise: i32,
         param: &[u8],
         bin: &[u8],
-    ) -> Result<Self, String> {
+    ) -> Result<Self, RealCuganError> {
+        Self::with_options(gpu, threads, tta, sync_gap, tile_size, scale, noise, &NativeOptions::default(), param, bin)
+    }
+
+    fn set_ncnn_options(realcugan: *mut c_void, options: &[(String, String)]) -> Result<(), RealCuganError> {
+        for (key, value) in options {
+            let enabled = match value.as_str() {
+                "1" | "true" | "on" => true,
+                "0" | "false" | "off" => false,
+                _ => return Err(RealCuganError::InvalidOption(format!("{} must be true or false, got {}", key, value))),
+            };
+            let c_key = CString::new(key.as_str())
+                .map_err(|_| RealCuganError::InvalidOption(format!("{:?} contains a nul byte", key)))?;
+            if unsafe { realcugan_set_option(realcugan, c_key.as_ptr(), enabled) } != 0 {
+                return Err(RealCuganError::InvalidOption(format!("unknown key {}", key)))
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn with_options(
+        gpu: i32,
+        threads: i32,
+        tta: bool,
+        sync_gap: i32,
+        tile_size: i32,
+        scale: i32,
+        noise: i32,
+        options: &NativeOptions,
+        param: &[u8],
+        bin: &[u8],
+    ) -> Result<Self, RealCuganError> {
+        if param.is_empty() || bin.is_empty() {
+            return Err(RealCuganError::NoModelProvided)
+        }
+        let _lifecycle = lifecycle();
         Self::validate_gpu(gpu)?;
         let prepading = Self::calculate_prepadding(scale)?;
         let tile_size = Self::calculate_tile_size(tile_size, scale, gpu);
-        let pointer = unsafe { realcugan_init(gpu,tta, threads) };
-        Self::load_model(pointer, param, bin)?;
+        let setup = Setup {
+            threads: Self::resolve_threads(threads),
+            tta,
+            options: options.clone(),
+            param: param.to_vec(),
+            bin: bin.to_vec(),
+        };
+        let parameters = Parameters {
+            scale,
+            noise,
+            prepadding: prepading,
+            sync_gap,
+            tile_size,
+        };
+        let pointer = Self::create_native(gpu, &setup, &parameters)?;
+
+        INSTANCES.fetch_add(1, Ordering::AcqRel);
+
+        Ok(Self {
+            pointer: Arc::new(NativeHandle(AtomicPtr::new(pointer))),
+            parameters: Arc::new(Mutex::new(parameters)),
+            device: Arc::new(Device {
+                gpu: AtomicI32::new(gpu),
+                allow_sync_gap: AtomicBool::new(true),
+                lock: RwLock::new(()),
+                setup: Some(Mutex::new(setup)),
+            }),
+            keep_input_channels: false,
+            match_input_type: false,
+            auto_retry_oom: false,
+            passthrough: None,
+            linear_light: false,
+            premultiplied_alpha: false,
+            tile_strategy: TileStrategy::Auto,
+            tile_lock: None,
+            tile_hook: None,
+            timeout: None,
+            tile_overlap: 0,
+            output_bit_depth: None,
+            jpeg_quality: None,
+            max_in_flight: None,
+            #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+            auto_noise: None,
+        })
+    }
+
+    // 0 means one thread per logical core.
+    fn resolve_threads(threads: i32) -> i32 {
+        if threads != 0 {
+            return threads
+        }
+        std::thread::available_parallelism()
+            .map_or(1, |count| i32::try_from(count.get()).unwrap_or(i32::MAX))
+    }
+
+    fn create_native(gpu: i32, setup: &Setup, parameters: &Parameters) -> Result<*mut c_void, RealCuganError> {
+        let precision = setup.options.precision.map_or(0, |precision| precision.as_i32());
+        let pointer = unsafe { realcugan_init(gpu, setup.tta, setup.threads, precision) };
+        if let Err(e) = Self::set_ncnn_options(pointer, &setup.options.ncnn)
+            .and_then(|_| Self::load_weights(pointer, &setup.param, &setup.bin))
+        {
+            unsafe { realcugan_free(pointer) }
+            return Err(e)
+        }
+        Self::apply_parameters(pointer, parameters);
+        Ok(pointer)
+    }
+
+    pub(crate) fn passthrough(scale: i32, filter: FilterType) -> Result<Self, RealCuganError> {
+        let parameters = Parameters {
+            scale,
+            noise: -1,
+            prepadding: Self::calculate_prepadding(scale)?,
+            sync_gap: 0,
+            tile_size: 0,
+        };
+
+        Ok(Self {
+            pointer: Arc::new(NativeHandle(AtomicPtr::new(std::ptr::null_mut()))),
+            parameters: Arc::new(Mutex::new(parameters)),
+            device: Arc::new(Device {
+                gpu: AtomicI32::new(-1),
+                allow_sync_gap: AtomicBool::new(false),
+                lock: RwLock::new(()),
+                setup: None,
+            }),
+            keep_input_channels: false,
+            match_input_type: false,
+            auto_retry_oom: false,
+            passthrough: Some(filter),
+            linear_light: false,
+            premultiplied_alpha: false,
+            tile_strategy: TileStrategy::Auto,
+            tile_lock: None,
+            tile_hook: None,
+            timeout: None,
+            tile_overlap: 0,
+            output_bit_depth: None,
+            jpeg_quality: None,
+            max_in_flight: None,
+            #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+            auto_noise: None,
+        })
+    }
 
+    fn apply_parameters(pointer: *mut c_void, parameters: &Parameters) {
         unsafe {
             realcugan_set_parameters(
                 pointer,
-                scale,
-                noise,
-                prepading,
-                sync_gap,
-                tile_size
+                parameters.scale,
+                parameters.noise,
+                parameters.prepadding,
+                parameters.sync_gap,
+                parameters.tile_size
             );
         }
+    }
 
-        INSTANCES.fetch_add(1, Ordering::Relaxed);
-
-        Ok(Self {
-            pointer: Arc::new(AtomicPtr::new(pointer)),
-            scale_factor: scale,
-            use_cpu: gpu == -1,
-        })
+    fn parameters(&self) -> Parameters {
+        *self.parameters.lock().unwrap_or_else(|e| e.into_inner())
     }
 
-    #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
-    pub fn from_model(model: Model) -> Self {
-        Builder::new().model(model).unwrap()
+    // only called with the device write lock held, since it changes the
+    // native parameters under any calls running on other clones
+    fn shrink_tile_size(&self, pointer: *mut c_void) -> bool {
+        let mut parameters = self.parameters.lock().unwrap_or_else(|e| e.into_inner());
+        if parameters.tile_size <= MIN_TILE_SIZE {
+            return false
+        }
+        parameters.tile_size = (parameters.tile_size / 2).max(MIN_TILE_SIZE);
+        Self::apply_parameters(pointer, &parameters);
+        true
     }
 
-    pub fn build<'a>() -> Builder<'a> {
-        Builder::new()
+    fn gpu(&self) -> i32 {
+        self.device.gpu.load(Ordering::Acquire)
     }
 
-    fn convert_image(width: u32, height: u32, channels: u8, bytes: Vec<u8>) -> Result<DynamicImage, String> {
-        match channels {
-            4 => RgbaImage::from_raw(width, height, bytes).map(DynamicImage::from),
-            3 => RgbImage::from_raw(width, height, bytes).map(DynamicImage::from),
-            2 => GrayAlphaImage::from_raw(width, height, bytes).map(DynamicImage::from),
-            1 => GrayImage::from_raw(width, height, bytes).map(DynamicImage::from),
-            _ => None
-        }.ok_or(format!("invalid number of channels: {}. expected 1, 2, 3, or 4", channels))
+    fn device_guard(&self) -> std::sync::RwLockReadGuard<'_, ()> {
+        self.device.lock.read().unwrap_or_else(|e| e.into_inner())
     }
 
-    fn prepare_image(&self, image: DynamicImage) -> (DynamicImage, u8) {
-        let bytes_per_pixel = image.color().bytes_per_pixel();
-        match bytes_per_pixel {
-            1 => (DynamicImage::from(image.to_rgb8()), 3),
-            2 => (DynamicImage::from(image.to_rgba8()), 4),
-            _ => (image, bytes_per_pixel),
+    // The new instance is created and loaded before the old one is freed, so
+    // a failed rebind leaves the handle working on its previous gpu.
+    pub fn rebind_gpu(&self, gpu: i32) -> Result<(), RealCuganError> {
+        let Some(setup) = &self.device.setup else {
+            return Err(RealCuganError::InvalidPointer)
+        };
+        let _device = self.device.lock.write().unwrap_or_else(|e| e.into_inner());
+        let _lifecycle = lifecycle();
+        Self::validate_gpu(gpu)?;
+        let setup = setup.lock().unwrap_or_else(|e| e.into_inner());
+
+        let mut parameters = self.parameters.lock().unwrap_or_else(|e| e.into_inner());
+        let mut rebound = *parameters;
+        if self.tile_strategy == TileStrategy::Auto {
+            rebound.tile_size = Self::calculate_tile_size(0, rebound.scale, gpu);
         }
-    }
+        let pointer = Self::create_native(gpu, &setup, &rebound)?;
 
-    fn create_input_buffer(&self, image: &DynamicImage, channels: u8) -> Result<Image, String> {
-        Ok(Image {
-            data: image.as_bytes().as_ptr(),
-            w: i32::try_from(image.width()).map_err(|e| format!("invalid width: {}", e))?,
-            h: i32::try_from(image.height()).map_err(|e| format!("invalid height: {}", e))?,
-            c: i32::from(channels),
-        })
+        let current = self.pointer.swap(pointer, Ordering::AcqRel);
+        if current.is_null() {
+            INSTANCES.fetch_add(1, Ordering::AcqRel);
+        } else {
+            unsafe { realcugan_free(current) }
+        }
+        self.device.gpu.store(gpu, Ordering::Release);
+        *parameters = rebound;
+        Ok(())
     }
 
-    fn create_output_buffer(&self, in_buffer: &Image, channels: u8) -> Image {
-        Image {
-            data: std::ptr::null_mut(),
-            w: in_buffer.w * self.scale_factor,
-            h: in_buffer.h * self.scale_factor,
-            c: i32::from(channels),
+    pub fn backend(&self) -> Backend {
+        match u32::try_from(self.gpu()) {
+            Ok(gpu) => Backend::Gpu(gpu),
+            Err(_) => Backend::Cpu,
         }
     }
 
-    fn process(&self, in_buffer: Image, out_buffer: Image, channels: u8) -> Result<DynamicImage, String> {
-        let mut mat_ptr = std::ptr::null_mut();
+    // The net and its pipelines are rebuilt for the new weights, but the
+    // device and the Vulkan instance stay. On failure the previous model is
+    // loaded back; if that fails too the original error is returned and the
+    // restore error goes to the ncnn-logs callback. Raw model bytes allow a sync gap, as they do
+    // in the builder.
+    pub fn load_model(&self, param: &[u8], bin: &[u8], scale: i32, noise: i32) -> Result<(), RealCuganError> {
+        let Some(setup) = &self.device.setup else {
+            return Err(RealCuganError::InvalidPointer)
+        };
+        let prepadding = Self::calculate_prepadding(scale)?;
+        Self::check_param(param)?;
+        Self::check_bin(bin)?;
+        let _device = self.device.lock.write().unwrap_or_else(|e| e.into_inner());
         let ptr = self.pointer.load(Ordering::Acquire);
         if ptr.is_null() {
-            return Err(format!("invalid pointer"))
+            return Err(RealCuganError::InvalidPointer)
         }
-
-        let result = if self.use_cpu {
-            unsafe {
-                realcugan_process_cpu(
-                    ptr,
-                    &in_buffer,
-                    &out_buffer,
-                    &mut mat_ptr,
-                )
-            }
-        } else {
-            unsafe {
-                realcugan_process(
-                    ptr,
-                    &in_buffer,
-                    &out_buffer,
-                    &mut mat_ptr,
-                )
+        let mut setup = setup.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = Self::load_weights(ptr, param, bin) {
+            if let Err(_restore) = Self::load_weights(ptr, &setup.param, &setup.bin) {
+                #[cfg(feature = "ncnn-logs")]
+                crate::log::emit(&format!("failed to restore the previous model: {}", _restore));
             }
-        };
-
-        if result != 0 {
-            unsafe { realcugan_free_image(mat_ptr) };
-            return Err(format!("failed to process image"))
+            return Err(e)
         }
+        setup.param = param.to_vec();
+        setup.bin = bin.to_vec();
+        self.device.allow_sync_gap.store(true, Ordering::Release);
 
-        let length = usize::try_from(out_buffer.h * out_buffer.w * out_buffer.c)
-            .map_err(|e| format!("invalid buffer length: {}", e))?;
+        let mut parameters = self.parameters.lock().unwrap_or_else(|e| e.into_inner());
+        parameters.scale = scale;
+        parameters.noise = noise;
+        parameters.prepadding = prepadding;
+        if self.tile_strategy == TileStrategy::Auto {
+            parameters.tile_size = Self::calculate_tile_size(0, scale, self.gpu());
+        }
+        Self::apply_parameters(ptr, &parameters);
+        Ok(())
+    }
 
-        let copied_bytes = unsafe { std::slice::from_raw_parts(out_buffer.data as *const u8, length).to_vec() };
-        unsafe { realcugan_free_image(mat_ptr) }
+    pub fn expected_channels(&self) -> Option<u8> {
+        let setup = self.device.setup.as_ref()?.lock().unwrap_or_else(|e| e.into_inner());
+        Self::input_channels(&setup.param)
+    }
 
-        Self::convert_image(
-            out_buffer.w as u32,
-            out_buffer.h as u32,
-            channels,
-            copied_bytes,
-        )
+    pub(crate) fn prepadding(&self) -> u32 {
+        self.parameters().prepadding as u32
     }
 
-    pub fn process_image(&self, image: DynamicImage) -> Result<DynamicImage, String> {
-        let (image, channels) = self.prepare_image(image);
-        let input_buffer = self.create_input_buffer(&image, channels)?;
-        let output_buffer = self.create_output_buffer(&input_buffer, channels);
-        self.process(input_buffer, output_buffer, channels)
+    pub fn native_scale(&self) -> i32 {
+        self.parameters().scale
     }
 
-    pub fn process_raw_image(&self, image: &[u8]) -> Result<Vec<u8>, String> {
-        let format = image::guess_format(image).unwrap_or(image::ImageFormat::Png);
-        image::load_from_memory(image)
-            .map_err(|x| format!("failed to load raw image: {}", x))
-            .and_then(|i| self.process_image(i))
-            .and_then(|i| {
-                let mut bytes = Cursor::new(Vec::new());
-                i.write_to(&mut bytes, format)
-                    .map_err(|e| format!("Failed to write to buffer: {}", e))
-                    .map(|_| bytes.into_inner())
-            })
+    // None as well for outputs the native code cannot address.
+    pub fn output_buffer_len(&self, input_w: u32, input_h: u32, channels: u8) -> Option<usize> {
+        let scale = u64::try_from(self.parameters().scale).ok()?;
+        let output = Self::output_image(u64::from(input_w) * scale, u64::from(input_h) * scale, channels).ok()?;
+        Self::output_length(&output).ok()
     }
 
-    pub fn process_image_from_path<P: AsRef<Path>>(&self, path: &P) -> Result<DynamicImage, String> {
-        let image = image::open(path)
-            .map_err(|x| format!("failed to open image from path: {}", x))?;
-        self.process_image(image)
+    // What the instance runs with, after models without sync gap support and
+    // .deterministic() have had their say.
+    pub fn effective_sync_gap(&self) -> SyncGap {
+        SyncGap::from_i32(self.parameters().sync_gap)
     }
 
-}
+    pub fn set_sync_gap(&self, gap: SyncGap) -> Result<(), RealCuganError> {
+        let _device = self.device.lock.write().unwrap_or_else(|e| e.into_inner());
+        let ptr = self.pointer.load(Ordering::Acquire);
+        if ptr.is_null() {
+            return Err(RealCuganError::InvalidPointer)
+        }
+        let mut parameters = self.parameters.lock().unwrap_or_else(|e| e.into_inner());
+        parameters.sync_gap = if self.device.allow_sync_gap.load(Ordering::Acquire) { gap.as_i32() } else { 0 };
+        Self::apply_parameters(ptr, &parameters);
+        Ok(())
+    }
+
+    #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+    #[track_caller]
+    pub fn from_model(model: Model) -> Self {
+        Builder::new().model(model).unwrap()
+    }
+
+    #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+    fn shared_instances() -> &'static Mutex<std::collections::HashMap<Model, Arc<RealCugan>>> {
+        static SHARED: std::sync::OnceLock<Mutex<std::collections::HashMap<Model, Arc<RealCugan>>>> = std::sync::OnceLock::new();
+        SHARED.get_or_init(|| Mutex::new(std::collections::HashMap::new()))
+    }
+
+    #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+    pub fn shared(model: Model) -> Result<Arc<RealCugan>, RealCuganError> {
+        let mut shared = Self::shared_instances().lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(realcugan) = shared.get(&model) {
+            return Ok(realcugan.clone())
+        }
+        let realcugan = Arc::new(Builder::new().model(model).build()?);
+        shared.insert(model, realcugan.clone());
+        Ok(realcugan)
+    }
+
+    #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+    pub fn clear_shared() {
+        Self::shared_instances().lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    pub fn estimate_noise_level(image: &DynamicImage) -> i32 {
+        crate::noise::estimate_level(image)
+    }
+
+    // The instance that runs this image under Builder::auto_noise(), chosen
+    // once per call so strips and tiles of one image share a model. None
+    // when auto_noise is off.
+    #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+    fn auto_noise_instance(&self, image: &DynamicImage) -> Result<Option<RealCugan>, RealCuganError> {
+        let Some(auto_noise) = &self.auto_noise else {
+            return Ok(None)
+        };
+        let model = auto_noise.select(image);
+        if model == auto_noise.model {
+            return Ok(Some(RealCugan { auto_noise: None, ..self.clone() }))
+        }
+        let mut instances = auto_noise.instances.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(realcugan) = instances.get(&model) {
+            return Ok(Some(realcugan.clone()))
+        }
+        let realcugan = auto_noise.builder.clone().model(model).build()?;
+        instances.insert(model, realcugan.clone());
+        Ok(Some(realcugan))
+    }
+
+    #[cfg(not(any(feature = "models-nose", feature = "models-pro", feature = "models-se")))]
+    fn auto_noise_instance(&self, _image: &DynamicImage) -> Result<Option<RealCugan>, RealCuganError> {
+        Ok(None)
+    }
+
+    pub fn compare(a: &DynamicImage, b: &DynamicImage) -> Result<QualityMetrics, RealCuganError> {
+        metrics::compare(a, b)
+    }
+
+    pub fn from_bytes(param: &[u8], bin: &[u8]) -> Result<Self, RealCuganError> {
+        Builder::new().model_bytes(param, bin).build()
+    }
+
+    fn read_model_file(path: &Path, kind: &str) -> Result<Vec<u8>, RealCuganError> {
+        let bytes = std::fs::read(path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => RealCuganError::Io(format!("{} file {} does not exist", kind, path.display())),
+            _ => RealCuganError::Io(format!("failed to read {} file {}: {}", kind, path.display(), e)),
+        })?;
+        if bytes.is_empty() {
+            return Err(RealCuganError::Io(format!("{} file {} is empty", kind, path.display())))
+        }
+        Ok(bytes)
+    }
+
+    // Checks what can be checked without a gpu, with the offending path in
+    // the error, for paths that come from users.
+    pub fn try_from_files<P: AsRef<Path>, B: AsRef<Path>>(param_path: P, bin_path: B) -> Result<Self, RealCuganError> {
+        let (param_path, bin_path) = (param_path.as_ref(), bin_path.as_ref());
+        let param = Self::read_model_file(param_path, "param")?;
+        let bin = Self::read_model_file(bin_path, "bin")?;
+        if Self::check_param(&bin).is_ok() {
+            return Err(RealCuganError::ModelMismatch(format!(
+                "{} is a param file, the param and bin paths may be swapped", bin_path.display()
+            )))
+        }
+        let with_path = |path: &Path, result: Result<(), RealCuganError>| result.map_err(|e| match e {
+            RealCuganError::ModelMismatch(message) => RealCuganError::ModelMismatch(format!("{}: {}", path.display(), message)),
+            e => e,
+        });
+        with_path(param_path, Self::check_param(&param))?;
+        with_path(bin_path, Self::check_bin(&bin))?;
+        Self::from_bytes(&param, &bin)
+    }
+
+    pub fn build<'a>() -> Builder<'a> {
+        Builder::new()
+    }
+
+    pub(crate) fn convert_image(width: u32, height: u32, channels: u8, bytes: Vec<u8>) -> Result<DynamicImage, RealCuganError> {
+        match channels {
+            4 => RgbaImage::from_raw(width, height, bytes).map(DynamicImage::from),
+            3 => RgbImage::from_raw(width, height, bytes).map(DynamicImage::from),
+            2 => GrayAlphaImage::from_raw(width, height, bytes).map(DynamicImage::from),
+            1 => GrayImage::from_raw(width, height, bytes).map(DynamicImage::from),
+            _ => None
+        }.ok_or(RealCuganError::InvalidChannels(channels))
+    }
+
+    pub(crate) fn keep_input_channels(mut self, keep: bool) -> Self {
+        self.keep_input_channels = keep;
+        self
+    }
+
+    pub(crate) fn match_input_type(mut self, match_input: bool) -> Self {
+        self.match_input_type = match_input;
+        self
+    }
+
+    pub(crate) fn auto_retry_oom(mut self, retry: bool) -> Self {
+        self.auto_retry_oom = retry;
+        self
+    }
+
+    pub(crate) fn allow_sync_gap(self, allow: bool) -> Self {
+        self.device.allow_sync_gap.store(allow, Ordering::Release);
+        self
+    }
+
+    pub(crate) fn linear_light(mut self, linear_light: bool) -> Self {
+        self.linear_light = linear_light;
+        self
+    }
+
+    pub(crate) fn premultiplied_alpha(mut self, premultiplied: bool) -> Self {
+        self.premultiplied_alpha = premultiplied;
+        self
+    }
+
+    pub(crate) fn tile_strategy(mut self, strategy: TileStrategy) -> Self {
+        let per_image = matches!(strategy, TileStrategy::None | TileStrategy::Adaptive);
+        self.tile_strategy = strategy;
+        self.tile_lock = per_image.then(|| Arc::new(Mutex::new(())));
+        self
+    }
+
+    // Splits the image into as few tiles as the current heap budget allows,
+    // all of about the same size, instead of full tiles and a thin remainder.
+    fn adaptive_tile_size(&self, width: u32, height: u32) -> u32 {
+        let scale = self.parameters().scale;
+        let budget = Self::calculate_tile_size(0, scale, self.gpu()).max(MIN_TILE_SIZE) as u32;
+        let columns = width.div_ceil(budget).max(1);
+        let rows = height.div_ceil(budget).max(1);
+        width.div_ceil(columns).max(height.div_ceil(rows)).max(1)
+    }
+
+    fn tile_size_for(&self, width: u32, height: u32) -> u32 {
+        match self.tile_strategy {
+            TileStrategy::None => width.max(height),
+            TileStrategy::Adaptive => self.adaptive_tile_size(width, height),
+            TileStrategy::Auto | TileStrategy::Fixed(_) => self.parameters().tile_size as u32,
+        }
+    }
+
+    fn image_tile_size(&self, image: &DynamicImage) -> u32 {
+        self.tile_size_for(image.width(), image.height())
+    }
+
+    // Uses the same table as the automatic tile size, so it is an estimate
+    // for one call at a time. Without a gpu or a reported budget there is
+    // nothing to check against.
+    pub fn will_fit(&self, input_w: u32, input_h: u32) -> bool {
+        let gpu = self.gpu();
+        if self.passthrough.is_some() || gpu == -1 {
+            return true
+        }
+        let budget = u64::from(unsafe { realcugan_get_heap_budget(gpu) }) * 1024 * 1024;
+        if budget == 0 {
+            return true
+        }
+        let tile_size = i32::try_from(self.tile_size_for(input_w, input_h)).unwrap_or(i32::MAX);
+        Self::estimate_vram(tile_size, self.parameters().scale, input_w, input_h) <= budget
+    }
+
+    pub(crate) fn tile_hook(mut self, hook: Option<TileHook>) -> Self {
+        self.tile_hook = hook;
+        self
+    }
+
+    pub(crate) fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub(crate) fn jpeg_quality(mut self, quality: Option<u8>) -> Self {
+        self.jpeg_quality = quality;
+        self
+    }
+
+    pub(crate) fn max_in_flight(mut self, images: Option<usize>) -> Self {
+        self.max_in_flight = images;
+        self
+    }
+
+    #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+    pub(crate) fn auto_noise(mut self, auto_noise: Option<Arc<AutoNoise>>) -> Self {
+        self.auto_noise = auto_noise;
+        self
+    }
+
+    pub(crate) fn output_bit_depth(mut self, depth: Option<u8>) -> Self {
+        self.output_bit_depth = depth;
+        self
+    }
+
+    pub(crate) fn tile_overlap(mut self, overlap: u32) -> Self {
+        self.tile_overlap = overlap;
+        self
+    }
+
+    // The tile size lives on the shared native instance, so calls that
+    // resize it to their own image hold the lock until they are done.
+    fn fit_tile_size(&self, image: &DynamicImage) -> Option<MutexGuard<'_, ()>> {
+        let lock = self.tile_lock.as_ref()?;
+        let guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        let _device = self.device_guard();
+        let ptr = self.pointer.load(Ordering::Acquire);
+        if !ptr.is_null() {
+            let tile_size = self.image_tile_size(image) as i32;
+            let mut parameters = self.parameters.lock().unwrap_or_else(|e| e.into_inner());
+            parameters.tile_size = tile_size;
+            Self::apply_parameters(ptr, &parameters);
+        }
+        Some(guard)
+    }
+
+    // The native code takes 8 bit samples, so 16 bit and float images are
+    // reduced to the 8 bit type with the same channels first.
+    fn prepare_image<'a>(&self, image: Cow<'a, DynamicImage>) -> (Cow<'a, DynamicImage>, u8) {
+        let channels = image.color().channel_count();
+        let eight_bit = image.color().bytes_per_pixel() == channels;
+        if self.keep_input_channels {
+            if eight_bit {
+                return (image, channels)
+            }
+            return match channels {
+                1 => (Cow::Owned(DynamicImage::from(image.to_luma8())), 1),
+                2 => (Cow::Owned(DynamicImage::from(image.to_luma_alpha8())), 2),
+                4 => (Cow::Owned(DynamicImage::from(image.to_rgba8())), 4),
+                _ => (Cow::Owned(DynamicImage::from(image.to_rgb8())), 3),
+            }
+        }
+        match channels {
+            3 | 4 if eight_bit => (image, channels),
+            2 | 4 => (Cow::Owned(DynamicImage::from(image.to_rgba8())), 4),
+            _ => (Cow::Owned(DynamicImage::from(image.to_rgb8())), 3),
+        }
+    }
+
+    // Dimensions are kept as u64 until they cross into the native code,
+    // which takes plain ints, so overflow surfaces as an error instead of
+    // wrapping.
+    fn to_c_int(value: u64, name: &str) -> Result<c_int, RealCuganError> {
+        c_int::try_from(value)
+            .map_err(|_| RealCuganError::InvalidDimensions(format!("{} {} does not fit the native image size", name, value)))
+    }
+
+    fn create_input_buffer<'a>(&self, image: &'a DynamicImage, channels: u8) -> Result<InputImage<'a>, RealCuganError> {
+        Ok(InputImage::new(
+            image.as_bytes(),
+            Self::to_c_int(u64::from(image.width()), "width")?,
+            Self::to_c_int(u64::from(image.height()), "height")?,
+            c_int::from(channels),
+        ))
+    }
+
+    // The native code also computes byte offsets into the output as ints,
+    // so the whole buffer has to fit one, not only each side.
+    fn output_image(width: u64, height: u64, channels: u8) -> Result<Image, RealCuganError> {
+        let output = Image {
+            data: std::ptr::null_mut(),
+            w: Self::to_c_int(width, "output width")?,
+            h: Self::to_c_int(height, "output height")?,
+            c: c_int::from(channels),
+        };
+        Self::to_c_int(width * height * u64::from(channels), "output size")?;
+        Ok(output)
+    }
+
+    fn create_output_buffer(&self, in_buffer: &InputImage, channels: u8) -> Result<Image, RealCuganError> {
+        let scale = u64::try_from(self.parameters().scale).unwrap_or(1);
+        let width = u64::try_from(in_buffer.raw().w).unwrap_or(0) * scale;
+        let height = u64::try_from(in_buffer.raw().h).unwrap_or(0) * scale;
+        Self::output_image(width, height, channels)
+    }
+
+    fn run(&self, ptr: *mut c_void, in_buffer: &InputImage, out_buffer: &mut Image, mat_ptr: &mut *mut c_void) -> c_int {
+        if self.gpu() == -1 {
+            unsafe { realcugan_process_cpu(ptr, in_buffer.raw(), out_buffer, mat_ptr) }
+        } else {
+            unsafe { realcugan_process(ptr, in_buffer.raw(), out_buffer, mat_ptr) }
+        }
+    }
+
+    fn process_mat(&self, in_buffer: &InputImage, out_buffer: &mut Image) -> Result<*mut c_void, RealCuganError> {
+        let mut mat_ptr = std::ptr::null_mut();
+        let mut result = {
+            let _device = self.device_guard();
+            let ptr = self.pointer.load(Ordering::Acquire);
+            if ptr.is_null() {
+                return Err(RealCuganError::InvalidPointer)
+            }
+            self.run(ptr, in_buffer, out_buffer, &mut mat_ptr)
+        };
+
+        if ProcessErrorKind::from_code(result) == ProcessErrorKind::OutOfMemory && self.auto_retry_oom {
+            // the tile size is shared with calls running on other clones, so
+            // it is only changed, and the retries run, while they are shut out
+            let _device = self.device.lock.write().unwrap_or_else(|e| e.into_inner());
+            let ptr = self.pointer.load(Ordering::Acquire);
+            while ProcessErrorKind::from_code(result) == ProcessErrorKind::OutOfMemory
+                && !ptr.is_null()
+                && self.shrink_tile_size(ptr)
+            {
+                unsafe { realcugan_free_image(mat_ptr) };
+                mat_ptr = std::ptr::null_mut();
+                result = self.run(ptr, in_buffer, out_buffer, &mut mat_ptr);
+            }
+        }
+
+        if result != 0 {
+            unsafe { realcugan_free_image(mat_ptr) };
+            return Err(RealCuganError::Process { code: result, kind: ProcessErrorKind::from_code(result) })
+        }
+        Ok(mat_ptr)
+    }
+
+    fn output_length(out_buffer: &Image) -> Result<usize, RealCuganError> {
+        let dimension = |value: c_int| u64::try_from(value).unwrap_or(0);
+        dimension(out_buffer.w)
+            .checked_mul(dimension(out_buffer.h))
+            .and_then(|pixels| pixels.checked_mul(dimension(out_buffer.c)))
+            .and_then(|length| usize::try_from(length).ok())
+            .ok_or_else(|| RealCuganError::InvalidDimensions(format!("output buffer {}x{}x{} is too large", out_buffer.w, out_buffer.h, out_buffer.c)))
+    }
+
+    fn process(&self, in_buffer: InputImage, mut out_buffer: Image, channels: u8) -> Result<DynamicImage, RealCuganError> {
+        let mat_ptr = self.process_mat(&in_buffer, &mut out_buffer)?;
+        let length = match Self::output_length(&out_buffer) {
+            Ok(length) => length,
+            Err(e) => {
+                unsafe { realcugan_free_image(mat_ptr) };
+                return Err(e)
+            }
+        };
+
+        let copied_bytes = unsafe { std::slice::from_raw_parts(out_buffer.data as *const u8, length).to_vec() };
+        unsafe { realcugan_free_image(mat_ptr) }
+
+        Self::convert_image(
+            out_buffer.w as u32,
+            out_buffer.h as u32,
+            channels,
+            copied_bytes,
+        )
+    }
+
+    fn resize_image(&self, image: &DynamicImage, filter: FilterType) -> Result<DynamicImage, RealCuganError> {
+        let scale = self.parameters().scale as u32;
+        let width = image.width().checked_mul(scale)
+            .ok_or(RealCuganError::InvalidDimensions(format!("invalid width: {}", image.width())))?;
+        let height = image.height().checked_mul(scale)
+            .ok_or(RealCuganError::InvalidDimensions(format!("invalid height: {}", image.height())))?;
+        Ok(image.resize_exact(width, height, filter))
+    }
+
+    // The native call cannot be interrupted, so on timeout the worker thread
+    // is left detached, still running on the gpu with its own clone, and its
+    // result is dropped when it finishes. The channel only disconnects when
+    // the worker panicked, and the panic is passed on to the caller.
+    fn process_with_timeout(&self, image: DynamicImage, timeout: Duration, options: ProcessOptions) -> Result<DynamicImage, RealCuganError> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let realcugan = self.clone().timeout(None);
+        let worker = std::thread::spawn(move || {
+            let _ = sender.send(realcugan.process_image_with_options(image, &options));
+        });
+        match receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => Err(RealCuganError::Timeout(timeout)),
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => match worker.join() {
+                Err(panic) => std::panic::resume_unwind(panic),
+                Ok(()) => unreachable!("the worker sends its result before it returns"),
+            },
+        }
+    }
+
+    pub fn process_image(&self, image: DynamicImage) -> Result<DynamicImage, RealCuganError> {
+        self.process_cow(Cow::Owned(image), &ProcessOptions::default())
+    }
+
+    pub fn process_image_with_options(&self, image: DynamicImage, options: &ProcessOptions) -> Result<DynamicImage, RealCuganError> {
+        self.process_cow(Cow::Owned(image), options)
+    }
+
+    // Only copies the image when it has to be converted, or when it has to
+    // be moved to the background thread of a timeout.
+    pub fn process_image_ref(&self, image: &DynamicImage) -> Result<DynamicImage, RealCuganError> {
+        self.process_cow(Cow::Borrowed(image), &ProcessOptions::default())
+    }
+
+    pub fn process_hdr_image(&self, image: &DynamicImage) -> Result<DynamicImage, RealCuganError> {
+        let mut tone_mapped = DynamicImage::from(color::tone_map(&image.to_rgba32f()));
+        if !image.color().has_alpha() {
+            tone_mapped = DynamicImage::from(tone_mapped.to_rgb8());
+        }
+        let output = DynamicImage::from(color::inverse_tone_map(&self.process_image(tone_mapped)?.to_rgba8()));
+        if image.color().has_alpha() {
+            Ok(output)
+        } else {
+            Ok(DynamicImage::from(output.to_rgb32f()))
+        }
+    }
+
+    #[cfg(feature = "exr")]
+    pub fn process_hdr_path_to_path<P: AsRef<Path>, Q: AsRef<Path>>(&self, input: P, output: Q) -> Result<(), RealCuganError> {
+        let image = image::open(input)
+            .map_err(|e| RealCuganError::Image(format!("failed to open image: {}", e)))?;
+        self.process_hdr_image(&image)?
+            .save(output)
+            .map_err(|e| RealCuganError::Image(format!("failed to save image: {}", e)))
+    }
+
+    // Upscales only when both sides are strictly smaller than the limits, so
+    // an image with either side at or above its limit is returned as is.
+    pub fn process_image_if_smaller_than(&self, image: DynamicImage, max_width: u32, max_height: u32) -> Result<DynamicImage, RealCuganError> {
+        if image.width() >= max_width || image.height() >= max_height {
+            return Ok(image)
+        }
+        self.process_image(image)
+    }
+
+    fn process_cow(&self, image: Cow<'_, DynamicImage>, options: &ProcessOptions) -> Result<DynamicImage, RealCuganError> {
+        if let Some(realcugan) = self.auto_noise_instance(&image)? {
+            return realcugan.process_cow(image, options)
+        }
+        let color = image.color();
+        let output = self.process_any(image, options)?;
+        let output = if self.match_input_type { color::to_color_type(output, color) } else { output };
+        Ok(match self.output_bit_depth {
+            Some(depth) => color::to_bit_depth(output, depth),
+            None => output,
+        })
+    }
+
+    fn process_any(&self, image: Cow<'_, DynamicImage>, options: &ProcessOptions) -> Result<DynamicImage, RealCuganError> {
+        options.check_cancelled()?;
+        if let Some(filter) = self.passthrough {
+            return self.resize_image(&image, filter)
+        }
+        if let Some(timeout) = options.timeout.or(self.timeout) {
+            return self.process_with_timeout(image.into_owned(), timeout, options.without_timeout())
+        }
+        if self.tile_overlap > 0 && self.tile_strategy != TileStrategy::None {
+            return self.process_overlapping(&image, options)
+        }
+        if self.tile_hook.is_some() || options.needs_tiles() {
+            return self.process_tiled(&image, options)
+        }
+        self.process_single(image)
+    }
+
+    // Tiles are cut in Rust for the tile hook and for progress and
+    // cancellation. Every tile is processed with the model padding around it
+    // and cropped back, so the result matches the native tiling.
+    fn rust_tiles(&self, image: &DynamicImage) -> Vec<TileRect> {
+        let padding = self.parameters().prepadding as u32;
+        let (width, height) = (image.width(), image.height());
+        let tile_size = match self.tile_strategy {
+            TileStrategy::None => width.max(height),
+            _ => self.image_tile_size(image).saturating_sub(padding * 2).max(1),
+        };
+        tile::grid(width, height, tile_size)
+    }
+
+    // Upscales one tile with the model padding around it, cropped back to the
+    // tile and passed through the tile hook.
+    fn upscale_tile(&self, image: &DynamicImage, tile: &TileRect) -> Result<(Vec<u8>, u8), RealCuganError> {
+        let parameters = self.parameters();
+        let scale = parameters.scale as u32;
+        let padding = parameters.prepadding as u32;
+        let left = tile.x.saturating_sub(padding);
+        let top = tile.y.saturating_sub(padding);
+        let right = (tile.x + tile.width + padding).min(image.width());
+        let bottom = (tile.y + tile.height + padding).min(image.height());
+        let upscaled = self.process_single(Cow::Owned(image.crop_imm(left, top, right - left, bottom - top)))?;
+        let upscaled = upscaled.crop_imm((tile.x - left) * scale, (tile.y - top) * scale, tile.width * scale, tile.height * scale);
+        let channels = upscaled.color().channel_count();
+        let mut bytes = upscaled.into_bytes();
+        if let Some(hook) = &self.tile_hook {
+            hook.call(&mut bytes, tile.width * scale, tile.height * scale);
+        }
+        Ok((bytes, channels))
+    }
+
+    fn process_tiled(&self, image: &DynamicImage, options: &ProcessOptions) -> Result<DynamicImage, RealCuganError> {
+        let scale = self.parameters().scale as u32;
+        let (width, height) = (image.width(), image.height());
+
+        let mut output: Option<(Vec<u8>, u8)> = None;
+        let row_length = |channels: u8| (width * scale) as usize * usize::from(channels);
+        let tiles = self.rust_tiles(image);
+        for (index, tile) in tiles.iter().enumerate() {
+            options.check_cancelled()?;
+            let (bytes, channels) = self.upscale_tile(image, tile)?;
+
+            let (pixels, _) = output.get_or_insert_with(|| {
+                (vec![0; row_length(channels) * (height * scale) as usize], channels)
+            });
+            let tile = tile.scaled(scale);
+            let tile_row = tile.width as usize * usize::from(channels);
+            for (row, source) in bytes.chunks_exact(tile_row).enumerate() {
+                let start = (tile.y as usize + row) * row_length(channels) + tile.x as usize * usize::from(channels);
+                pixels[start..start + tile_row].copy_from_slice(source);
+            }
+            options.report(index + 1, tiles.len());
+        }
+        let (pixels, channels) = output
+            .ok_or(RealCuganError::InvalidDimensions(format!("empty image: {}x{}", width, height)))?;
+        Self::convert_image(width * scale, height * scale, channels, pixels)
+    }
+
+    // Tiles are cut the same way as in process_tiled, and only the ones that
+    // touch a dirty region are upscaled and pasted over the previous output.
+    pub fn process_image_incremental(&self, image: &DynamicImage, dirty_regions: &[TileRect], previous: &DynamicImage) -> Result<DynamicImage, RealCuganError> {
+        if self.passthrough.is_some() {
+            return self.process_image_ref(image)
+        }
+        let scale = self.parameters().scale as u32;
+        let expected = (u64::from(image.width()) * u64::from(scale), u64::from(image.height()) * u64::from(scale));
+        if expected != (u64::from(previous.width()), u64::from(previous.height())) {
+            return Err(RealCuganError::InvalidDimensions(format!(
+                "previous output is {}x{}, expected {}x{}", previous.width(), previous.height(), expected.0, expected.1
+            )))
+        }
+
+        let mut output = previous.clone();
+        for tile in self.rust_tiles(image) {
+            if !dirty_regions.iter().any(|region| tile.intersects(region)) {
+                continue
+            }
+            let (bytes, channels) = self.upscale_tile(image, &tile)?;
+            let upscaled = Self::convert_image(tile.width * scale, tile.height * scale, channels, bytes)?;
+            let tile = tile.scaled(scale);
+            image::imageops::replace(&mut output, &color::to_color_type(upscaled, previous.color()), i64::from(tile.x), i64::from(tile.y));
+        }
+        Ok(output)
+    }
+
+    fn overlapping_tiles(&self, width: u32, height: u32) -> Vec<TileRect> {
+        let tile_size = self.parameters().tile_size as u32;
+        tile::overlapping_grid(width, height, tile_size, self.tile_overlap)
+    }
+
+    // Each tile is no larger than the native tile size, so the native code
+    // processes it in one piece and all seams are blended here.
+    fn process_overlapping(&self, image: &DynamicImage, options: &ProcessOptions) -> Result<DynamicImage, RealCuganError> {
+        let scale = self.parameters().scale as u32;
+        let (width, height) = (image.width(), image.height());
+        let mut blender: Option<(Blender, u8)> = None;
+        let tiles = self.overlapping_tiles(width, height);
+        for (index, &tile) in tiles.iter().enumerate() {
+            options.check_cancelled()?;
+            let output = self.process_single(Cow::Owned(image.crop_imm(tile.x, tile.y, tile.width, tile.height)))?;
+            let channels = output.color().channel_count();
+            let mut bytes = output.into_bytes();
+            if let Some(hook) = &self.tile_hook {
+                hook.call(&mut bytes, tile.width * scale, tile.height * scale);
+            }
+            let (blender, _) = blender.get_or_insert_with(|| {
+                (Blender::new(width * scale, height * scale, channels, self.tile_overlap * scale), channels)
+            });
+            blender.add(tile.scaled(scale), &bytes);
+            options.report(index + 1, tiles.len());
+        }
+        let (blender, channels) = blender
+            .ok_or(RealCuganError::InvalidDimensions(format!("empty image: {}x{}", width, height)))?;
+        Self::convert_image(width * scale, height * scale, channels, blender.finish())
+    }
+
+    // The model works on straight alpha, and linear light is converted from
+    // the straight color values.
+    fn decode_pixels(&self, image: &mut Cow<'_, DynamicImage>) {
+        if self.premultiplied_alpha && image.color().has_alpha() {
+            color::unpremultiply(image.to_mut());
+        }
+        if self.linear_light {
+            color::to_linear(image.to_mut());
+        }
+    }
+
+    fn process_single(&self, image: Cow<'_, DynamicImage>) -> Result<DynamicImage, RealCuganError> {
+        let (mut image, channels) = self.prepare_image(image);
+        self.decode_pixels(&mut image);
+        let _tiling = self.fit_tile_size(&image);
+        let input_buffer = self.create_input_buffer(&image, channels)?;
+        let output_buffer = self.create_output_buffer(&input_buffer, channels)?;
+        let mut output = self.process(input_buffer, output_buffer, channels)?;
+        if self.linear_light {
+            color::to_srgb(&mut output);
+        }
+        if self.premultiplied_alpha {
+            color::premultiply(&mut output);
+        }
+        Ok(output)
+    }
+
+    // The ncnn buffer is handed out as is, so settings that cut the tiles on
+    // the Rust side or convert the output are rejected instead of ignored.
+    pub fn process_image_zerocopy(&self, image: DynamicImage) -> Result<OutputBuffer, RealCuganError> {
+        if let Some(realcugan) = self.auto_noise_instance(&image)? {
+            return realcugan.process_image_zerocopy(image)
+        }
+        let color = image.color();
+        let (mut image, channels) = self.prepare_image(Cow::Owned(image));
+        let unsupported = [
+            ("timeout", self.timeout.is_some()),
+            ("tile_hook", self.tile_hook.is_some()),
+            ("tile_overlap", self.tile_overlap > 0 && self.tile_strategy != TileStrategy::None),
+            ("match_input_type", self.match_input_type && image.color() != color),
+            ("output_bit_depth", self.output_bit_depth.is_some_and(|depth| depth != 8)),
+        ];
+        if let Some((option, _)) = unsupported.iter().find(|(_, set)| *set) {
+            return Err(RealCuganError::Unsupported(format!("{} is not supported by process_image_zerocopy", option)))
+        }
+        self.decode_pixels(&mut image);
+        let _tiling = self.fit_tile_size(&image);
+        let input_buffer = self.create_input_buffer(&image, channels)?;
+        let mut output_buffer = self.create_output_buffer(&input_buffer, channels)?;
+        let mat = self.process_mat(&input_buffer, &mut output_buffer)?;
+        let mut output = OutputBuffer {
+            mat,
+            data: output_buffer.data as *mut u8,
+            length: 0,
+            width: output_buffer.w as u32,
+            height: output_buffer.h as u32,
+            channels,
+        };
+        output.length = Self::output_length(&output_buffer)?;
+        if self.linear_light {
+            color::bytes_to_srgb(&mut output, channels);
+        }
+        if self.premultiplied_alpha {
+            color::bytes_premultiply(&mut output, channels);
+        }
+        Ok(output)
+    }
+
+    pub fn process_with_residual(&self, image: &DynamicImage) -> Result<(DynamicImage, DynamicImage), RealCuganError> {
+        let output = self.process_image_ref(image)?;
+        let baseline = self.resize_image(image, FilterType::CatmullRom)?;
+        let residual = metrics::residual(&output, &baseline);
+        Ok((output, DynamicImage::from(residual)))
+    }
+
+    // Takes the same branches as process_any() does for the default options.
+    pub fn process_image_debug(&self, image: DynamicImage) -> Result<(DynamicImage, Vec<TileRect>), RealCuganError> {
+        let scale = self.parameters().scale as u32;
+        let tiles = if self.passthrough.is_some() {
+            Vec::new()
+        } else if self.tile_overlap > 0 && self.tile_strategy != TileStrategy::None {
+            self.overlapping_tiles(image.width(), image.height())
+        } else if self.tile_hook.is_some() {
+            self.rust_tiles(&image)
+        } else {
+            tile::grid(image.width(), image.height(), self.image_tile_size(&image))
+        };
+        let tiles = tiles.iter().map(|tile| tile.scaled(scale)).collect();
+        self.process_image(image).map(|output| (output, tiles))
+    }
+
+    pub fn process_image_pyramid(&self, image: DynamicImage, levels: &[u32]) -> Result<Vec<DynamicImage>, RealCuganError> {
+        let upscaled = self.process_image(image)?;
+        let longest = upscaled.width().max(upscaled.height());
+        Ok(levels
+            .iter()
+            .map(|&level| if level >= longest {
+                upscaled.clone()
+            } else {
+                upscaled.resize(level, level, FilterType::Lanczos3)
+            })
+            .collect())
+    }
+
+    pub fn process_iter<'a, I>(&'a self, frames: I) -> impl Iterator<Item = Result<DynamicImage, RealCuganError>> + 'a
+    where
+        I: Iterator<Item = DynamicImage> + 'a
+    {
+        frames.map(move |frame| self.process_image(frame))
+    }
+
+    pub fn process_rgb(&self, image: RgbImage) -> Result<RgbImage, RealCuganError> {
+        self.process_image(DynamicImage::from(image)).map(DynamicImage::into_rgb8)
+    }
+
+    pub fn process_rgba(&self, image: RgbaImage) -> Result<RgbaImage, RealCuganError> {
+        self.process_image(DynamicImage::from(image)).map(DynamicImage::into_rgba8)
+    }
+
+    pub fn process_yuv420(&self, y: &[u8], u: &[u8], v: &[u8], w: u32, h: u32) -> Result<RgbImage, RealCuganError> {
+        let luma_length = w as usize * h as usize;
+        let chroma_length = w.div_ceil(2) as usize * h.div_ceil(2) as usize;
+        if y.len() < luma_length || u.len() < chroma_length || v.len() < chroma_length {
+            return Err(RealCuganError::InvalidDimensions(format!(
+                "yuv420 planes of {}, {} and {} bytes are too small for {}x{}",
+                y.len(), u.len(), v.len(), w, h
+            )))
+        }
+        self.process_rgb(color::yuv420_to_rgb(y, u, v, w, h))
+    }
+
+    // Rows are repacked into a tight buffer, the padding after each row is
+    // never read, so the last row does not need it.
+    pub fn process_strided(&self, data: &[u8], width: u32, height: u32, channels: u8, row_stride: usize) -> Result<DynamicImage, RealCuganError> {
+        let row_length = width as usize * usize::from(channels);
+        if row_stride < row_length {
+            return Err(RealCuganError::InvalidDimensions(format!(
+                "row stride {} is smaller than a row of {} bytes", row_stride, row_length
+            )))
+        }
+        let required = match height {
+            0 => Some(0),
+            _ => row_stride.checked_mul(height as usize - 1).and_then(|length| length.checked_add(row_length)),
+        };
+        let Some(required) = required.filter(|&required| data.len() >= required) else {
+            return Err(RealCuganError::InvalidDimensions(format!(
+                "{} bytes are too small for {}x{} with a row stride of {}", data.len(), width, height, row_stride
+            )))
+        };
+        let bytes = if row_stride == row_length {
+            data[..required].to_vec()
+        } else {
+            data.chunks(row_stride)
+                .take(height as usize)
+                .flat_map(|row| &row[..row_length])
+                .copied()
+                .collect()
+        };
+        self.process_image(Self::convert_image(width, height, channels, bytes)?)
+    }
+
+    pub fn process_raw_image(&self, image: &[u8]) -> Result<Vec<u8>, RealCuganError> {
+        self.process_raw_image_detailed(image).map(|(bytes, _)| bytes)
+    }
+
+    pub fn process_raw_image_detailed(&self, image: &[u8]) -> Result<(Vec<u8>, image::ImageFormat), RealCuganError> {
+        let format = image::guess_format(image).unwrap_or(image::ImageFormat::Png);
+        Self::load_from_memory(image)
+            .and_then(|i| self.process_image(i))
+            .and_then(|i| {
+                let mut bytes = Cursor::new(Vec::new());
+                let written = match (format, self.jpeg_quality) {
+                    (ImageFormat::Jpeg, Some(quality)) => i.write_with_encoder(image::codecs::jpeg::JpegEncoder::new_with_quality(&mut bytes, quality)),
+                    _ => i.write_to(&mut bytes, format),
+                };
+                written
+                    .map_err(|e| RealCuganError::Image(format!("Failed to write to buffer: {}", e)))
+                    .map(|_| (bytes.into_inner(), format))
+            })
+    }
+
+    fn load_from_memory(bytes: &[u8]) -> Result<DynamicImage, RealCuganError> {
+        #[cfg(feature = "heif")]
+        if heif::is_heif(bytes) {
+            return heif::decode(bytes)
+        }
+        image::load_from_memory(bytes)
+            .map_err(|x| RealCuganError::Image(format!("failed to load raw image: {}", x)))
+    }
+
+    fn open<P: AsRef<Path>>(path: &P) -> Result<DynamicImage, RealCuganError> {
+        #[cfg(feature = "heif")]
+        if heif::is_heif_path(path) {
+            let bytes = std::fs::read(path)
+                .map_err(|e| RealCuganError::Io(format!("failed to open image from path: {}", e)))?;
+            return heif::decode(&bytes)
+        }
+        image::open(path)
+            .map_err(|x| RealCuganError::Image(format!("failed to open image from path: {}", x)))
+    }
+
+    pub fn process_image_from_path<P: AsRef<Path>>(&self, path: &P) -> Result<DynamicImage, RealCuganError> {
+        let image = Self::open(path)?;
+        self.process_image(image)
+    }
+
+    // A DynamicImage carries no color profile, so it has to be passed along
+    // separately to be kept, e.g. from read_icc_profile() of the source file.
+    pub fn process_image_to_path<P: AsRef<Path>>(&self, image: DynamicImage, path: &P) -> Result<(), RealCuganError> {
+        self.process_image_to_path_with_icc_profile(image, path, None)
+    }
+
+    pub fn process_image_to_path_with_icc_profile<P: AsRef<Path>>(&self, image: DynamicImage, path: &P, icc_profile: Option<Vec<u8>>) -> Result<(), RealCuganError> {
+        let upscaled = self.process_image(image)?;
+        self.save_with_icc_profile(&upscaled, path, icc_profile)
+    }
+
+    pub fn read_icc_profile<P: AsRef<Path>>(path: &P) -> Result<Option<Vec<u8>>, RealCuganError> {
+        let mut decoder = image::ImageReader::open(path)
+            .and_then(|reader| reader.with_guessed_format())
+            .map_err(|e| RealCuganError::Io(format!("failed to open image from path: {}", e)))?
+            .into_decoder()
+            .map_err(|x| RealCuganError::Image(format!("failed to open image from path: {}", x)))?;
+        decoder.icc_profile().map_err(|x| RealCuganError::Image(format!("failed to read icc profile: {}", x)))
+    }
+
+    // Only the png, jpeg and webp encoders can embed a profile, other formats
+    // are written without it.
+    fn save_with_icc_profile<P: AsRef<Path>>(&self, image: &DynamicImage, path: &P, icc_profile: Option<Vec<u8>>) -> Result<(), RealCuganError> {
+        fn encode(image: &DynamicImage, mut encoder: impl ImageEncoder, icc_profile: Option<Vec<u8>>) -> image::ImageResult<()> {
+            if let Some(icc_profile) = icc_profile {
+                let _ = encoder.set_icc_profile(icc_profile);
+            }
+            image.write_with_encoder(encoder)
+        }
+
+        let format = ImageFormat::from_path(path)
+            .map_err(|x| RealCuganError::Image(format!("failed to save image to path: {}", x)))?;
+        let file = || std::fs::File::create(path)
+            .map(std::io::BufWriter::new)
+            .map_err(|e| RealCuganError::Io(format!("failed to create output file: {}", e)));
+        match format {
+            ImageFormat::Png => encode(image, image::codecs::png::PngEncoder::new(file()?), icc_profile),
+            ImageFormat::Jpeg => match self.jpeg_quality {
+                Some(quality) => encode(image, image::codecs::jpeg::JpegEncoder::new_with_quality(file()?, quality), icc_profile),
+                None => encode(image, image::codecs::jpeg::JpegEncoder::new(file()?), icc_profile),
+            },
+            ImageFormat::WebP => encode(image, image::codecs::webp::WebPEncoder::new_lossless(file()?), icc_profile),
+            _ => image.save(path),
+        }.map_err(|x| RealCuganError::Image(format!("failed to save image to path: {}", x)))
+    }
+
+    pub fn process_path_to_path<P: AsRef<Path>, Q: AsRef<Path>>(&self, input: &P, output: &Q) -> Result<(), RealCuganError> {
+        #[cfg(feature = "heif")]
+        if heif::is_heif_path(input) {
+            let upscaled = self.process_image(Self::open(input)?)?;
+            return self.save_with_icc_profile(&upscaled, output, None)
+        }
+        let mut decoder = image::ImageReader::open(input)
+            .and_then(|reader| reader.with_guessed_format())
+            .map_err(|e| RealCuganError::Io(format!("failed to open image from path: {}", e)))?
+            .into_decoder()
+            .map_err(|x| RealCuganError::Image(format!("failed to open image from path: {}", x)))?;
+        let icc_profile = decoder.icc_profile().ok().flatten();
+        let image = DynamicImage::from_decoder(decoder)
+            .map_err(|x| RealCuganError::Image(format!("failed to decode image: {}", x)))?;
+        let upscaled = self.process_image(image)?;
+        self.save_with_icc_profile(&upscaled, output, icc_profile)
+    }
+
+    pub fn self_test(&self) -> Result<(), RealCuganError> {
+        const SIZE: u32 = 4;
+
+        let scale = self.parameters().scale as u32;
+        let image = RgbImage::from_pixel(SIZE, SIZE, image::Rgb([128, 64, 32]));
+        let output = self.process_image(DynamicImage::from(image))?;
+
+        if output.width() != SIZE * scale || output.height() != SIZE * scale {
+            return Err(RealCuganError::SelfTest(format!(
+                "expected {}x{} output, got {}x{}",
+                SIZE * scale, SIZE * scale, output.width(), output.height()
+            )))
+        }
+        if output.as_bytes().is_empty() {
+            return Err(RealCuganError::SelfTest("empty output buffer".to_string()))
+        }
+        Ok(())
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.self_test().is_ok()
+    }
+
+    pub fn warmup(&self) -> Result<(), RealCuganError> {
+        if self.passthrough.is_some() {
+            return Ok(())
+        }
+        let size = self.parameters().tile_size.max(MIN_TILE_SIZE) as u32;
+        let image = RgbImage::from_pixel(size, size, image::Rgb([128, 128, 128]));
+        self.process_image(DynamicImage::from(image)).map(|_| ())
+    }
+
+    pub fn benchmark(&self, image: &DynamicImage, iterations: u32) -> Result<BenchResult, RealCuganError> {
+        self.benchmark_with_warmup(image, 1, iterations)
+    }
+
+    // Warmup runs are processed the same way but left out of the statistics.
+    pub fn benchmark_with_warmup(&self, image: &DynamicImage, warmup: u32, iterations: u32) -> Result<BenchResult, RealCuganError> {
+        for _ in 0..warmup {
+            self.process_image_ref(image)?;
+        }
+        let mut samples = Vec::with_capacity(iterations.max(1) as usize);
+        for _ in 0..iterations.max(1) {
+            let start = Instant::now();
+            self.process_image_ref(image)?;
+            samples.push(start.elapsed());
+        }
+        Ok(BenchResult::from_samples(samples))
+    }
+
+    #[cfg(feature = "rayon")]
+    pub fn process_images_par(&self, images: &[DynamicImage]) -> Vec<Result<DynamicImage, RealCuganError>> {
+        use rayon::prelude::*;
+
+        let Some(limit) = self.max_in_flight else {
+            return images
+                .par_iter()
+                .map_init(|| self.clone(), |realcugan, image| realcugan.process_image(image.clone()))
+                .collect()
+        };
+
+        // Pool threads past the limit wait here instead of starting another
+        // image. The slot is given back on drop so a panicking call cannot
+        // leave the others waiting forever.
+        struct Slot<'a>(&'a (Mutex<usize>, std::sync::Condvar));
+        impl Drop for Slot<'_> {
+            fn drop(&mut self) {
+                *self.0.0.lock().unwrap_or_else(|e| e.into_inner()) -= 1;
+                self.0.1.notify_one();
+            }
+        }
+
+        let in_flight = (Mutex::new(0), std::sync::Condvar::new());
+        images
+            .par_iter()
+            .map_init(|| self.clone(), |realcugan, image| {
+                let mut running = in_flight.1
+                    .wait_while(in_flight.0.lock().unwrap_or_else(|e| e.into_inner()), |running| *running >= limit.max(1))
+                    .unwrap_or_else(|e| e.into_inner());
+                *running += 1;
+                std::mem::drop(running);
+                let _slot = Slot(&in_flight);
+                realcugan.process_image(image.clone())
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "ndarray")]
+    pub fn process_ndarray(&self, array: ndarray::ArrayView3<u8>) -> Result<ndarray::Array3<u8>, RealCuganError> {
+        let (height, width, channels) = array.dim();
+        let channels = match u8::try_from(channels) {
+            Ok(channels @ (1 | 3 | 4)) => channels,
+            _ => return Err(RealCuganError::InvalidChannels(channels.min(u8::MAX as usize) as u8)),
+        };
+        let width = u32::try_from(width)
+            .map_err(|_| RealCuganError::InvalidDimensions(format!("invalid width: {}", width)))?;
+        let height = u32::try_from(height)
+            .map_err(|_| RealCuganError::InvalidDimensions(format!("invalid height: {}", height)))?;
+        let image = Self::convert_image(width, height, channels, array.iter().copied().collect())?;
+
+        // Gray input is processed as RGB, so it is converted back to keep the
+        // channel count of the input array.
+        let output = self.process_image(image)?;
+        let (width, height) = (output.width() as usize, output.height() as usize);
+        let bytes = match channels {
+            1 => output.into_luma8().into_raw(),
+            3 => output.into_rgb8().into_raw(),
+            _ => output.into_rgba8().into_raw(),
+        };
+        ndarray::Array3::from_shape_vec((height, width, usize::from(channels)), bytes)
+            .map_err(|e| RealCuganError::InvalidDimensions(format!("invalid output shape: {}", e)))
+    }
+
+    // Every strip is processed with the model padding above and below it, so
+    // the strips line up without seams.
+    pub fn process_image_streaming<F>(&self, image: &DynamicImage, chunk_rows: u32, mut sink: F) -> Result<(), RealCuganError>
+    where
+        F: FnMut(&[u8], u32)
+    {
+        if chunk_rows == 0 {
+            return Err(RealCuganError::InvalidDimensions("chunk rows must be at least 1".to_string()))
+        }
+        let mut row = 0;
+        self.process_strips(image, chunk_rows, |strip| {
+            sink(strip.as_bytes(), row);
+            row += strip.height();
+            Ok(())
+        })
+    }
+
+    // Strips are copied into the map as they finish, so only one strip of
+    // the output is held in memory. The file is sized from the first strip,
+    // since the output type depends on the input and the builder settings.
+    #[cfg(feature = "mmap")]
+    pub fn process_image_to_mmap<P: AsRef<Path>>(&self, image: &DynamicImage, path: P) -> Result<memmap2::Mmap, RealCuganError> {
+        let io_error = |e: std::io::Error| RealCuganError::Io(format!("failed to map output file: {}", e));
+        let parameters = self.parameters();
+        let scale = parameters.scale as u64;
+        let rows = (parameters.tile_size.max(MIN_TILE_SIZE)) as u32;
+        let length = |bytes_per_pixel: u8| u64::from(image.width())
+            .checked_mul(u64::from(image.height()))
+            .and_then(|pixels| pixels.checked_mul(scale * scale * u64::from(bytes_per_pixel)))
+            .ok_or(RealCuganError::InvalidDimensions(format!("output of {}x{} is too large", image.width(), image.height())));
+
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(io_error)?;
+        let mut map: Option<memmap2::MmapMut> = None;
+        let mut offset = 0;
+        self.process_strips(image, rows, |strip| {
+            let map = match &mut map {
+                Some(map) => map,
+                None => {
+                    file.set_len(length(strip.color().bytes_per_pixel())?).map_err(io_error)?;
+                    map.insert(unsafe { memmap2::MmapMut::map_mut(&file) }.map_err(io_error)?)
+                }
+            };
+            let bytes = strip.as_bytes();
+            map[offset..offset + bytes.len()].copy_from_slice(bytes);
+            offset += bytes.len();
+            Ok(())
+        })?;
+        let map = map.ok_or(RealCuganError::InvalidDimensions(format!("empty image: {}x{}", image.width(), image.height())))?;
+        map.flush().map_err(io_error)?;
+        map.make_read_only().map_err(io_error)
+    }
+
+    fn process_strips<F>(&self, image: &DynamicImage, rows: u32, mut sink: F) -> Result<(), RealCuganError>
+    where
+        F: FnMut(DynamicImage) -> Result<(), RealCuganError>
+    {
+        if let Some(realcugan) = self.auto_noise_instance(image)? {
+            return realcugan.process_strips(image, rows, sink)
+        }
+        let mut y = 0;
+        while y < image.height() {
+            sink(self.process_strip(image, y, rows)?)?;
+            y += rows.min(image.height() - y);
+        }
+        Ok(())
+    }
+
+    // Upscales the rows from y with enough of the neighbouring rows to pad
+    // the strip like a tile, then crops the padding off the result.
+    fn process_strip(&self, image: &DynamicImage, y: u32, rows: u32) -> Result<DynamicImage, RealCuganError> {
+        let parameters = self.parameters();
+        let scale = parameters.scale as u32;
+        let padding = parameters.prepadding as u32;
+        let (width, height) = (image.width(), image.height());
+
+        let strip_height = rows.min(height - y);
+        let top = y.saturating_sub(padding);
+        let bottom = (y + strip_height + padding).min(height);
+        let upscaled = self.process_image(image.crop_imm(0, top, width, bottom - top))?;
+        Ok(upscaled.crop_imm(0, (y - top) * scale, width * scale, strip_height * scale))
+    }
+
+    // The first strip has already been processed to pick the tiff color
+    // type, so it is written as is and the rest follow from its last row.
+    #[cfg(feature = "tiff")]
+    fn write_tiff_strips<C, W>(
+        &self,
+        encoder: &mut tiff::encoder::TiffEncoder<W>,
+        image: &DynamicImage,
+        rows: u32,
+        first: DynamicImage,
+        samples: fn(&DynamicImage) -> Option<&[C::Inner]>
+    ) -> Result<(), RealCuganError>
+    where
+        C: tiff::encoder::colortype::ColorType,
+        [C::Inner]: tiff::encoder::TiffValue,
+        W: Write + Seek
+    {
+        let scale = self.parameters().scale as u32;
+        let width = image.width().checked_mul(scale).ok_or(RealCuganError::InvalidDimensions(format!("invalid width: {}", image.width())))?;
+        let height = image.height().checked_mul(scale).ok_or(RealCuganError::InvalidDimensions(format!("invalid height: {}", image.height())))?;
+
+        let mut tiff_image = encoder.new_image::<C>(width, height)
+            .map_err(|e| RealCuganError::Image(format!("failed to create tiff image: {}", e)))?;
+        tiff_image.rows_per_strip(rows * scale)
+            .map_err(|e| RealCuganError::Image(format!("failed to set tiff strip size: {}", e)))?;
+
+        let color = first.color();
+        let mut strip = first;
+        let mut y = 0;
+        loop {
+            let data = samples(&strip)
+                .filter(|_| strip.color() == color)
+                .ok_or(RealCuganError::Image(format!("strip color type {:?} differs from {:?}", strip.color(), color)))?;
+            tiff_image.write_strip(data)
+                .map_err(|e| RealCuganError::Image(format!("failed to write tiff strip: {}", e)))?;
+            y += rows.min(image.height() - y);
+            if y >= image.height() {
+                break
+            }
+            strip = self.process_strip(image, y, rows)?;
+        }
+        tiff_image.finish()
+            .map_err(|e| RealCuganError::Image(format!("failed to finish tiff image: {}", e)))
+    }
+
+    // The tiff color type follows the processed output rather than the
+    // input, since output_bit_depth() and match_input_type() change it.
+    #[cfg(feature = "tiff")]
+    pub fn process_image_to_file<P: AsRef<Path>>(&self, image: DynamicImage, path: &P) -> Result<(), RealCuganError> {
+        use tiff::encoder::colortype::{Gray16, Gray8, RGB16, RGB8, RGBA16, RGBA8};
+
+        if let Some(realcugan) = self.auto_noise_instance(&image)? {
+            return realcugan.process_image_to_file(image, path)
+        }
+        if image.width() == 0 || image.height() == 0 {
+            return Err(RealCuganError::InvalidDimensions(format!("empty image: {}x{}", image.width(), image.height())))
+        }
+        // one strip per row of tiles, with the tile size the adaptive and
+        // untiled strategies resolve for this image rather than the stored one
+        let rows = self.image_tile_size(&image).max(1);
+        let first = self.process_strip(&image, 0, rows)?;
+
+        let file = std::fs::File::create(path)
+            .map_err(|e| RealCuganError::Io(format!("failed to create output file: {}", e)))?;
+        let mut encoder = tiff::encoder::TiffEncoder::new(std::io::BufWriter::new(file))
+            .map_err(|e| RealCuganError::Image(format!("failed to create tiff encoder: {}", e)))?;
+
+        match first {
+            DynamicImage::ImageRgb8(_) => self.write_tiff_strips::<RGB8, _>(&mut encoder, &image, rows, first, |strip| Some(strip.as_bytes())),
+            DynamicImage::ImageRgba8(_) => self.write_tiff_strips::<RGBA8, _>(&mut encoder, &image, rows, first, |strip| Some(strip.as_bytes())),
+            DynamicImage::ImageLuma8(_) => self.write_tiff_strips::<Gray8, _>(&mut encoder, &image, rows, first, |strip| Some(strip.as_bytes())),
+            DynamicImage::ImageRgb16(_) => self.write_tiff_strips::<RGB16, _>(&mut encoder, &image, rows, first, |strip| match strip {
+                DynamicImage::ImageRgb16(buffer) => Some(buffer.as_raw()),
+                _ => None,
+            }),
+            DynamicImage::ImageRgba16(_) => self.write_tiff_strips::<RGBA16, _>(&mut encoder, &image, rows, first, |strip| match strip {
+                DynamicImage::ImageRgba16(buffer) => Some(buffer.as_raw()),
+                _ => None,
+            }),
+            DynamicImage::ImageLuma16(_) => self.write_tiff_strips::<Gray16, _>(&mut encoder, &image, rows, first, |strip| match strip {
+                DynamicImage::ImageLuma16(buffer) => Some(buffer.as_raw()),
+                _ => None,
+            }),
+            _ => Err(RealCuganError::Image(format!("unsupported tiff color type: {:?}", first.color()))),
+        }
+    }
+
+    // 16 bit samples are reduced to 8 bit, like the rest of the pipeline.
+    #[cfg(feature = "tiff")]
+    fn decode_tiff_page<R: std::io::Read + Seek>(decoder: &mut tiff::decoder::Decoder<R>) -> Result<DynamicImage, RealCuganError> {
+        use tiff::decoder::DecodingResult;
+        use tiff::ColorType;
+
+        let tiff_error = |e: tiff::TiffError| RealCuganError::Image(format!("failed to decode tiff page: {}", e));
+        let (width, height) = decoder.dimensions().map_err(tiff_error)?;
+        let channels = match decoder.colortype().map_err(tiff_error)? {
+            ColorType::Gray(8 | 16) => 1,
+            ColorType::GrayA(8 | 16) => 2,
+            ColorType::RGB(8 | 16) => 3,
+            ColorType::RGBA(8 | 16) => 4,
+            _ => return Err(RealCuganError::Image("unsupported tiff color type".to_string())),
+        };
+        let bytes = match decoder.read_image().map_err(tiff_error)? {
+            DecodingResult::U8(bytes) => bytes,
+            DecodingResult::U16(samples) => samples.iter().map(|sample| (sample >> 8) as u8).collect(),
+            _ => return Err(RealCuganError::Image("unsupported tiff sample format".to_string())),
+        };
+        Self::convert_image(width, height, channels, bytes)
+    }
+
+    #[cfg(feature = "tiff")]
+    pub fn process_tiff_pages(&self, data: &[u8]) -> Result<Vec<DynamicImage>, RealCuganError> {
+        let tiff_error = |e: tiff::TiffError| RealCuganError::Image(format!("failed to decode tiff: {}", e));
+        let mut decoder = tiff::decoder::Decoder::new(Cursor::new(data)).map_err(tiff_error)?;
+        let mut pages = vec![self.process_image(Self::decode_tiff_page(&mut decoder)?)?];
+        while decoder.more_images() {
+            decoder.next_image().map_err(tiff_error)?;
+            pages.push(self.process_image(Self::decode_tiff_page(&mut decoder)?)?);
+        }
+        Ok(pages)
+    }
+
+    #[cfg(feature = "tiff")]
+    pub fn write_tiff_pages(pages: &[DynamicImage]) -> Result<Vec<u8>, RealCuganError> {
+        use tiff::encoder::colortype::{Gray8, RGB8, RGBA8};
+
+        let tiff_error = |e: tiff::TiffError| RealCuganError::Image(format!("failed to encode tiff page: {}", e));
+        let mut buffer = Cursor::new(Vec::new());
+        let mut encoder = tiff::encoder::TiffEncoder::new(&mut buffer).map_err(tiff_error)?;
+        for page in pages {
+            let (width, height) = (page.width(), page.height());
+            match page.color().channel_count() {
+                1 => encoder.write_image::<Gray8>(width, height, page.to_luma8().as_raw()),
+                2 | 4 => encoder.write_image::<RGBA8>(width, height, page.to_rgba8().as_raw()),
+                _ => encoder.write_image::<RGB8>(width, height, page.to_rgb8().as_raw()),
+            }.map_err(tiff_error)?;
+        }
+        Ok(buffer.into_inner())
+    }
+
+}
 
 impl Clone for RealCugan {
 
     fn clone(&self) -> Self {
         RealCugan {
             pointer: self.pointer.clone(),
-            scale_factor: self.scale_factor,
-            use_cpu: self.use_cpu,
+            parameters: self.parameters.clone(),
+            device: self.device.clone(),
+            keep_input_channels: self.keep_input_channels,
+            match_input_type: self.match_input_type,
+            auto_retry_oom: self.auto_retry_oom,
+            passthrough: self.passthrough,
+            linear_light: self.linear_light,
+            premultiplied_alpha: self.premultiplied_alpha,
+            tile_strategy: self.tile_strategy,
+            tile_lock: self.tile_lock.clone(),
+            tile_hook: self.tile_hook.clone(),
+            timeout: self.timeout,
+            tile_overlap: self.tile_overlap,
+            output_bit_depth: self.output_bit_depth,
+            jpeg_quality: self.jpeg_quality,
+            max_in_flight: self.max_in_flight,
+            #[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+            auto_noise: self.auto_noise.clone(),
         }
     }
 
 }
 
-impl Drop for RealCugan {
+impl TryFrom<(&Path, &Path)> for RealCugan {
+    type Error = RealCuganError;
+
+    fn try_from((param, bin): (&Path, &Path)) -> Result<Self, Self::Error> {
+        Self::try_from_files(param, bin)
+    }
+}
+
+impl TryFrom<(&[u8], &[u8])> for RealCugan {
+    type Error = RealCuganError;
+
+    fn try_from((param, bin): (&[u8], &[u8])) -> Result<Self, Self::Error> {
+        Self::from_bytes(param, bin)
+    }
+}
+
+#[cfg(any(feature = "models-nose", feature = "models-pro", feature = "models-se"))]
+impl TryFrom<Model> for RealCugan {
+    type Error = RealCuganError;
+
+    fn try_from(model: Model) -> Result<Self, Self::Error> {
+        Builder::new().model(model).build()
+    }
+}
+
+impl Drop for NativeHandle {
     fn drop(&mut self) {
-        if Arc::strong_count(&self.pointer) == 1 {
-            let ptr = self.pointer.load(Ordering::Acquire);
-            if !ptr.is_null() {
-                unsafe { realcugan_free(ptr) }
-            }
+        let ptr = *self.0.get_mut();
+        if ptr.is_null() {
+            return
+        }
+        let _lifecycle = lifecycle();
+        unsafe { realcugan_free(ptr) }
 
-            if INSTANCES.fetch_sub(1, Ordering::AcqRel) == 1 {
-                unsafe { realcugan_destroy_gpu_instance() }
+        if INSTANCES.fetch_sub(1, Ordering::AcqRel) == 1 {
+            unsafe { realcugan_destroy_gpu_instance() }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_image_size() {
+        assert!(RealCugan::output_image(1024, 1024, 4).is_ok());
+        // each side fits an int, the byte offsets into the buffer do not
+        let result = RealCugan::output_image(30000, 30000, 3);
+        assert!(matches!(result, Err(RealCuganError::InvalidDimensions(_))));
+        assert!(RealCugan::output_image(u64::from(u32::MAX), 1, 1).is_err());
+    }
+
+    #[test]
+    fn estimate_vram_round_trip() {
+        for scale in 2..=4 {
+            for &(_, tile_size) in RealCugan::tile_size_thresholds(scale) {
+                let bytes = RealCugan::estimate_vram(tile_size, scale, 4096, 4096);
+                let megabytes = (bytes / 1024 / 1024) as i32;
+                assert_eq!(RealCugan::budget_tile_size(megabytes, scale), tile_size, "scale {} tile size {}", scale, tile_size);
             }
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,234 @@
+use std::sync::Arc;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TileRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl TileRect {
+    pub(crate) fn scaled(&self, scale: u32) -> Self {
+        Self {
+            x: self.x * scale,
+            y: self.y * scale,
+            width: self.width * scale,
+            height: self.height * scale,
+        }
+    }
+
+    pub(crate) fn intersects(&self, other: &TileRect) -> bool {
+        self.x < other.x.saturating_add(other.width)
+            && other.x < self.x + self.width
+            && self.y < other.y.saturating_add(other.height)
+            && other.y < self.y + self.height
+    }
+}
+
+// Same layout as the tile loops in realcugan.cpp: a row-major grid of
+// tile_size squares, with the last row and column cut at the image edge.
+pub(crate) fn grid(width: u32, height: u32, tile_size: u32) -> Vec<TileRect> {
+    let tile_size = tile_size.max(1);
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(tile_size as usize) {
+        for x in (0..width).step_by(tile_size as usize) {
+            tiles.push(TileRect {
+                x,
+                y,
+                width: tile_size.min(width - x),
+                height: tile_size.min(height - y),
+            });
+        }
+    }
+    tiles
+}
+
+// Tiles of tile_size that advance by tile_size - overlap, with the last row
+// and column moved back so they end exactly at the image edge. The builder
+// rejects an overlap of half a fixed tile size or more, but an automatic
+// tile size can shrink below that, so the overlap is capped there as well.
+pub(crate) fn overlapping_grid(width: u32, height: u32, tile_size: u32, overlap: u32) -> Vec<TileRect> {
+    fn starts(length: u32, tile_size: u32, step: u32) -> Vec<u32> {
+        let mut starts = vec![0];
+        let mut start = 0;
+        while start + tile_size < length {
+            start = (start + step).min(length - tile_size);
+            starts.push(start);
+        }
+        starts
+    }
+
+    let tile_size = tile_size.max(1);
+    let step = tile_size - overlap.min((tile_size - 1) / 2);
+    let mut tiles = Vec::new();
+    for y in starts(height, tile_size, step) {
+        for x in starts(width, tile_size, step) {
+            tiles.push(TileRect {
+                x,
+                y,
+                width: tile_size.min(width - x),
+                height: tile_size.min(height - y),
+            });
+        }
+    }
+    tiles
+}
+
+type HookFn = dyn Fn(&mut [u8], u32, u32) + Send + Sync;
+
+#[derive(Clone)]
+pub(crate) struct TileHook(Arc<HookFn>);
+
+impl TileHook {
+    pub(crate) fn new(hook: impl Fn(&mut [u8], u32, u32) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(hook))
+    }
+
+    pub(crate) fn call(&self, bytes: &mut [u8], width: u32, height: u32) {
+        (self.0)(bytes, width, height)
+    }
+}
+
+impl std::fmt::Debug for TileHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TileHook")
+    }
+}
+
+pub(crate) struct Blender {
+    width: u32,
+    height: u32,
+    channels: usize,
+    overlap: u32,
+    values: Vec<f32>,
+    weights: Vec<f32>,
+}
+
+impl Blender {
+
+    pub(crate) fn new(width: u32, height: u32, channels: u8, overlap: u32) -> Self {
+        let pixels = width as usize * height as usize;
+        Self {
+            width,
+            height,
+            channels: usize::from(channels),
+            overlap,
+            values: vec![0.0; pixels * usize::from(channels)],
+            weights: vec![0.0; pixels],
+        }
+    }
+
+    // Linear ramp across the overlap on every side that borders another tile.
+    fn ramp(&self, offset: u32, length: u32, first: bool, last: bool) -> f32 {
+        let overlap = self.overlap.min(length / 2).max(1) as f32;
+        let mut weight: f32 = 1.0;
+        if !first {
+            weight = weight.min((offset as f32 + 0.5) / overlap);
+        }
+        if !last {
+            weight = weight.min(((length - offset) as f32 - 0.5) / overlap);
+        }
+        weight.min(1.0)
+    }
+
+    pub(crate) fn add(&mut self, tile: TileRect, bytes: &[u8]) {
+        let left = tile.x == 0;
+        let right = tile.x + tile.width == self.width;
+        let top = tile.y == 0;
+        let bottom = tile.y + tile.height == self.height;
+        for dy in 0..tile.height {
+            let weight_y = self.ramp(dy, tile.height, top, bottom);
+            for dx in 0..tile.width {
+                let weight = weight_y * self.ramp(dx, tile.width, left, right);
+                let pixel = (tile.y + dy) as usize * self.width as usize + (tile.x + dx) as usize;
+                let source = (dy as usize * tile.width as usize + dx as usize) * self.channels;
+                self.weights[pixel] += weight;
+                for channel in 0..self.channels {
+                    self.values[pixel * self.channels + channel] += f32::from(bytes[source + channel]) * weight;
+                }
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        let channels = self.channels;
+        self.values
+            .iter()
+            .enumerate()
+            .map(|(index, value)| {
+                let weight = self.weights[index / channels];
+                (value / weight).round().clamp(0.0, 255.0) as u8
+            })
+            .collect()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coverage(tiles: &[TileRect], width: u32, height: u32) -> Vec<u32> {
+        let mut counts = vec![0; (width * height) as usize];
+        for tile in tiles {
+            for y in tile.y..tile.y + tile.height {
+                for x in tile.x..tile.x + tile.width {
+                    counts[(y * width + x) as usize] += 1;
+                }
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn overlapping_grid_covers_image() {
+        let tiles = overlapping_grid(100, 70, 32, 8);
+        assert!(coverage(&tiles, 100, 70).iter().all(|count| *count > 0));
+        // full sized tiles, the last row and column end at the edge
+        assert!(tiles.iter().all(|tile| tile.width == 32 && tile.height == 32));
+        assert!(tiles.iter().any(|tile| tile.x + tile.width == 100));
+        assert!(tiles.iter().any(|tile| tile.y + tile.height == 70));
+        let starts: Vec<u32> = tiles.iter().filter(|tile| tile.y == 0).map(|tile| tile.x).collect();
+        assert_eq!(starts, vec![0, 24, 48, 68]);
+    }
+
+    #[test]
+    fn overlapping_grid_edges() {
+        // smaller than a tile
+        assert_eq!(overlapping_grid(20, 10, 32, 8), vec![TileRect { x: 0, y: 0, width: 20, height: 10 }]);
+        // exactly one tile
+        assert_eq!(overlapping_grid(32, 32, 32, 8).len(), 1);
+        // an overlap of the whole tile is capped below half of it
+        let tiles = overlapping_grid(64, 1, 16, 16);
+        let starts: Vec<u32> = tiles.iter().map(|tile| tile.x).collect();
+        assert_eq!(starts, vec![0, 9, 18, 27, 36, 45, 48]);
+    }
+
+    #[test]
+    fn blender_ramps_sum_to_one() {
+        let (width, height) = (100, 70);
+        let tiles = overlapping_grid(width, height, 32, 8);
+        let mut blender = Blender::new(width, height, 1, 8);
+        for tile in &tiles {
+            blender.add(*tile, &vec![200; (tile.width * tile.height) as usize]);
+        }
+        assert!(blender.weights.iter().all(|weight| *weight > 0.0));
+        assert!(blender.finish().iter().all(|value| *value == 200));
+    }
+
+    #[test]
+    fn blender_ramp() {
+        let blender = Blender::new(64, 64, 1, 8);
+        // edges of the image are not ramped
+        assert_eq!(blender.ramp(0, 32, true, true), 1.0);
+        assert!(blender.ramp(0, 32, false, true) < 0.1);
+        assert!(blender.ramp(31, 32, true, false) < 0.1);
+        assert_eq!(blender.ramp(16, 32, false, false), 1.0);
+        // the ramps of two tiles overlapping by 8 add up to one
+        for offset in 0..8 {
+            let sum = blender.ramp(24 + offset, 32, true, false) + blender.ramp(offset, 32, false, true);
+            assert!((sum - 1.0).abs() < 1e-6);
+        }
+    }
+}
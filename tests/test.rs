@@ -83,6 +83,40 @@ fn threads() {
     }
 }
 
+#[test]
+fn clone_drop() {
+    let build = || realcugan_rs::RealCugan::build()
+    .model_files(&format!("{}.param", MODEL),&format!("{}.bin", MODEL))
+    .scale(2)
+    .unwrap();
+    let image = image::DynamicImage::new_rgb8(16, 16);
+
+    // drop without clone
+    drop(build());
+
+    // clone, then drop both
+    let realcugan = build();
+    let clone = realcugan.clone();
+    drop(realcugan);
+    drop(clone);
+
+    // drop the original and keep using the clone
+    let realcugan = build();
+    let clone = realcugan.clone();
+    drop(realcugan);
+    assert!(clone.process_image(image.clone()).is_ok());
+
+    // drop clones from several threads at once
+    let clones: Vec<_> = (0..8).map(|_| clone.clone()).collect();
+    drop(clone);
+    let threads: Vec<_> = clones.into_iter().map(|clone| std::thread::spawn(move || drop(clone))).collect();
+    for thread in threads {
+        assert!(thread.join().is_ok());
+    }
+
+    assert!(build().process_image(image).is_ok());
+}
+
 #[cfg(feature = "models")]
 #[test]
 fn model() {
@@ -94,4 +128,186 @@ fn model() {
     upscaled_image.save_with_format(path, image::ImageFormat::Png).unwrap();
     assert!(Path::new(&path).exists(), "Failed to save upscaled image");
     let _ = std::fs::remove_file(&path);
-}
\ No newline at end of file
+}
+#[cfg(feature = "models-se")]
+#[test]
+fn auto_noise() {
+    let result = realcugan_rs::RealCugan::build()
+    .model_files(&format!("{}.param", MODEL),&format!("{}.bin", MODEL))
+    .auto_noise()
+    .build();
+    assert!(matches!(result, Err(realcugan_rs::RealCuganError::AutoNoiseWithoutModel)));
+
+    let realcugan = realcugan_rs::RealCugan::build()
+    .model(realcugan_rs::Model::Se2xNoDenoise)
+    .auto_noise()
+    .unwrap();
+    let image = image::open(IMAGE).expect("Failed to open test image");
+    let upscaled_image = realcugan.process_image(image.clone()).expect("Failed to upscale image");
+    assert_eq!(upscaled_image.width(), image.width() * 2);
+}
+
+#[test]
+fn model_blob() {
+    let param = std::fs::read(format!("{}.param", MODEL)).unwrap();
+    let bin = std::fs::read(format!("{}.bin", MODEL)).unwrap();
+    let blob = realcugan_rs::RealCugan::pack_model(&param, &bin);
+
+    let result = realcugan_rs::RealCugan::build()
+    .model_blob(&blob)
+    .scale(2)
+    .noise(-1)
+    .build();
+    assert!(result.is_ok(), "{}", result.err().unwrap().to_string());
+
+    let truncated = realcugan_rs::RealCugan::build()
+    .model_blob(&blob[..4])
+    .build();
+    assert!(matches!(truncated, Err(realcugan_rs::RealCuganError::InvalidModelBlob(_))));
+}
+
+#[test]
+fn compare() {
+    let image = image::open(IMAGE).expect("Failed to open test image");
+    let metrics = realcugan_rs::RealCugan::compare(&image, &image).unwrap();
+    assert!(metrics.psnr.is_infinite());
+    assert!((metrics.ssim - 1.0).abs() < 1e-9);
+
+    let blurred = image.blur(2.0);
+    let metrics = realcugan_rs::RealCugan::compare(&image, &blurred).unwrap();
+    assert!(metrics.psnr.is_finite() && metrics.ssim < 1.0);
+
+    let smaller = image.thumbnail(16, 16);
+    assert!(realcugan_rs::RealCugan::compare(&image, &smaller).is_err());
+}
+
+#[test]
+fn no_model() {
+    let result = realcugan_rs::RealCugan::build().build();
+    assert!(matches!(result, Err(realcugan_rs::RealCuganError::NoModelProvided)));
+}
+
+#[test]
+fn model_mismatch() {
+    let result = realcugan_rs::RealCugan::build()
+    .model_files(&format!("{}.param", MODEL), "./models/models-se/up4x-conservative.bin")
+    .scale(2)
+    .build();
+    assert!(matches!(result, Err(realcugan_rs::RealCuganError::ModelMismatch(_))));
+}
+
+#[test]
+fn try_from_files() {
+    let result = realcugan_rs::RealCugan::try_from_files("./models/missing.param", format!("{}.bin", MODEL));
+    assert!(matches!(result, Err(realcugan_rs::RealCuganError::Io(_))));
+
+    let result = realcugan_rs::RealCugan::try_from_files(format!("{}.bin", MODEL), format!("{}.param", MODEL));
+    assert!(matches!(result, Err(realcugan_rs::RealCuganError::ModelMismatch(_))));
+}
+
+#[test]
+fn expected_channels() {
+    let realcugan = realcugan_rs::RealCugan::build()
+    .model_files(&format!("{}.param", MODEL),&format!("{}.bin", MODEL))
+    .scale(2)
+    .unwrap();
+    assert_eq!(realcugan.expected_channels(), Some(3));
+}
+
+#[test]
+fn owned_image() {
+    let result = realcugan_rs::OwnedImage::new(vec![0; 10], 2, 2, 3);
+    assert!(matches!(result, Err(realcugan_rs::RealCuganError::InvalidDimensions(_))));
+
+    let image = image::open(IMAGE).expect("Failed to open test image").to_rgb8();
+    let (width, height) = image.dimensions();
+    let owned = realcugan_rs::OwnedImage::new(image.into_raw(), width, height, 3).unwrap();
+    assert_eq!(owned.as_bytes().len(), (width * height * 3) as usize);
+    let image = image::DynamicImage::try_from(owned).unwrap();
+    assert_eq!((image.width(), image.height()), (width, height));
+}
+
+#[test]
+fn rebind_invalid_gpu() {
+    let realcugan = realcugan_rs::RealCugan::build()
+    .model_files(&format!("{}.param", MODEL),&format!("{}.bin", MODEL))
+    .scale(2)
+    .unwrap();
+
+    let result = realcugan.rebind_gpu(9999);
+    assert!(matches!(result, Err(realcugan_rs::RealCuganError::GpuNotFound { gpu: 9999, .. })));
+
+    let image = image::open(IMAGE).expect("Failed to open test image");
+    assert!(realcugan.process_image(image).is_ok());
+}
+
+#[cfg(not(any(feature = "models-nose", feature = "models-pro", feature = "models-se")))]
+#[test]
+fn without_models() {
+    let result = realcugan_rs::RealCugan::build()
+    .model_files(&format!("{}.param", MODEL),&format!("{}.bin", MODEL))
+    .scale(2)
+    .build();
+    assert!(result.is_ok(), "{}", result.err().unwrap().to_string());
+
+    let param = std::fs::read(format!("{}.param", MODEL)).unwrap();
+    let bin = std::fs::read(format!("{}.bin", MODEL)).unwrap();
+    let result = realcugan_rs::RealCugan::build()
+    .model_bytes(&param, &bin)
+    .scale(2)
+    .build();
+    assert!(result.is_ok(), "{}", result.err().unwrap().to_string());
+}
+
+#[cfg(feature = "tiff")]
+#[test]
+fn tiff_bit_depth() {
+    let realcugan = realcugan_rs::RealCugan::build()
+    .model_files(&format!("{}.param", MODEL),&format!("{}.bin", MODEL))
+    .scale(2)
+    .noise(-1)
+    .output_bit_depth(16)
+    .unwrap();
+
+    let image = image::open(IMAGE).expect("Failed to open test image");
+    let path = "/tmp/upscaled16.tiff";
+    realcugan.process_image_to_file(image.clone(), &path).expect("Failed to write tiff");
+
+    let mut decoder = tiff::decoder::Decoder::new(std::fs::File::open(path).unwrap()).unwrap();
+    assert_eq!(decoder.colortype().unwrap(), tiff::ColorType::RGB(16));
+    assert_eq!(decoder.dimensions().unwrap(), (image.width() * 2, image.height() * 2));
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn strided() {
+    let realcugan = realcugan_rs::RealCugan::build()
+    .model_files(&format!("{}.param", MODEL),&format!("{}.bin", MODEL))
+    .scale(2)
+    .unwrap();
+
+    // rows of 8 bytes padded to 12, no padding after the last row
+    let data = vec![128; 12 * 3 + 8];
+    let result = realcugan.process_strided(&data, 2, 4, 4, 6);
+    assert!(matches!(result, Err(realcugan_rs::RealCuganError::InvalidDimensions(_))));
+    let result = realcugan.process_strided(&data[..data.len() - 1], 2, 4, 4, 12);
+    assert!(matches!(result, Err(realcugan_rs::RealCuganError::InvalidDimensions(_))));
+    let result = realcugan.process_strided(&data, 2, 4, 4, usize::MAX);
+    assert!(matches!(result, Err(realcugan_rs::RealCuganError::InvalidDimensions(_))));
+
+    let image = realcugan.process_strided(&data, 2, 4, 4, 12).unwrap();
+    assert_eq!((image.width(), image.height()), (4, 8));
+}
+
+#[test]
+fn output_buffer_len() {
+    let realcugan = realcugan_rs::RealCugan::build()
+    .model_files(&format!("{}.param", MODEL),&format!("{}.bin", MODEL))
+    .scale(2)
+    .unwrap();
+
+    assert_eq!(realcugan.output_buffer_len(100, 50, 3), Some(200 * 100 * 3));
+    assert_eq!(realcugan.output_buffer_len(u32::MAX, u32::MAX, 4), None);
+    // too large for the int offsets of the native code
+    assert_eq!(realcugan.output_buffer_len(20000, 20000, 4), None);
+}